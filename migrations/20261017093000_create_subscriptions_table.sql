@@ -0,0 +1,8 @@
+CREATE TABLE subscriptions(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    email TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    status TEXT NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE jobs(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    job_type TEXT NOT NULL,
+    payload jsonb NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX jobs_created_at_idx ON jobs (created_at);
@@ -0,0 +1,17 @@
+ALTER TABLE jobs
+    ADD COLUMN n_retries INT NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+
+DROP INDEX jobs_created_at_idx;
+CREATE INDEX jobs_execute_after_idx ON jobs (execute_after);
+
+CREATE TABLE dead_letter_jobs(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    job_type TEXT NOT NULL,
+    payload jsonb NOT NULL,
+    n_retries INT NOT NULL,
+    last_error TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    failed_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,5 @@
+CREATE TABLE scheduled_job_ticks(
+    name TEXT NOT NULL,
+    PRIMARY KEY (name),
+    last_tick_at timestamptz NOT NULL
+);
@@ -0,0 +1,19 @@
+CREATE TABLE newsletter_issues(
+    newsletter_issue_id uuid NOT NULL,
+    PRIMARY KEY (newsletter_issue_id),
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    published_at timestamptz NOT NULL
+);
+
+CREATE TABLE issue_delivery_queue(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    n_retries INT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
+
+CREATE INDEX issue_delivery_queue_execute_after_idx ON issue_delivery_queue (execute_after);
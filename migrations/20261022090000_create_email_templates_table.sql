@@ -0,0 +1,8 @@
+-- Overrides for the email templates shipped in `templates/`, keyed by the same
+-- path (e.g. `confirmation/body.html`).
+CREATE TABLE email_templates(
+    path TEXT NOT NULL,
+    PRIMARY KEY (path),
+    source TEXT NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
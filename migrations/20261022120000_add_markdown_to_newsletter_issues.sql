@@ -0,0 +1,13 @@
+-- The Markdown an issue was written in, if any. `html_content` and `text_content`
+-- hold its rendering.
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT;
+
+-- Links of an issue, as written. Emails point to `/newsletters/{id}/links/{index}`,
+-- which redirects here.
+CREATE TABLE newsletter_issue_links(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    link_index INT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, link_index)
+);
@@ -0,0 +1,18 @@
+-- Opens and clicks are only tracked for issues published with tracking on.
+ALTER TABLE newsletter_issues ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE newsletter_events(
+    id BIGSERIAL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    -- `open` or `click`.
+    kind TEXT NOT NULL,
+    -- The link that was clicked, see `newsletter_issue_links`.
+    link_index INT,
+    user_agent TEXT,
+    occurred_at timestamptz NOT NULL
+);
+
+CREATE INDEX newsletter_events_issue_idx ON newsletter_events (newsletter_issue_id, kind);
@@ -0,0 +1,15 @@
+-- Bounces, spam complaints and subscription changes reported by the email provider,
+-- kept to tell why an address stopped receiving mail.
+CREATE TABLE email_events(
+    id BIGSERIAL PRIMARY KEY,
+    -- As named by Postmark: `Bounce`, `SpamComplaint` or `SubscriptionChange`.
+    record_type TEXT NOT NULL,
+    email TEXT NOT NULL,
+    -- Whether the event stopped (`true`) or resumed (`false`) mailing the address.
+    suppressed BOOLEAN,
+    payload JSONB NOT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL
+);
+
+CREATE INDEX email_events_email_idx ON email_events (lower(email));
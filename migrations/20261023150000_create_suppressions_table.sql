@@ -0,0 +1,34 @@
+-- Addresses and whole domains that must never be mailed, whatever their status.
+CREATE TABLE suppressions(
+    -- `address` or `domain`.
+    kind TEXT NOT NULL,
+    -- A normalized email address, or a lowercased domain.
+    entry TEXT NOT NULL,
+    -- `hard_bounce`, `spam_complaint`, `provider` or `manual`.
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (kind, entry)
+);
+
+-- Addresses that bounced or complained, and were not reactivated since, before the
+-- list existed.
+INSERT INTO suppressions (kind, entry, reason, created_at)
+SELECT
+    'address',
+    email,
+    CASE record_type
+        WHEN 'Bounce' THEN 'hard_bounce'
+        WHEN 'SpamComplaint' THEN 'spam_complaint'
+        ELSE 'provider'
+    END,
+    occurred_at
+FROM (
+    SELECT DISTINCT ON (lower(email)) lower(email) AS email, record_type, suppressed, occurred_at
+    FROM email_events
+    WHERE suppressed IS NOT NULL
+    ORDER BY lower(email), occurred_at DESC
+) AS latest
+WHERE suppressed;
+
+-- Deliveries skipped because of the list are recorded in `newsletter_events`, with the
+-- `suppressed` kind.
@@ -0,0 +1,3 @@
+-- The status a subscriber had before being suppressed, restored when the provider
+-- reactivates the address. NULL unless `status` is `suppressed`.
+ALTER TABLE subscriptions ADD COLUMN suppressed_from TEXT NULL;
@@ -0,0 +1,3 @@
+-- Addresses that differ only in case belong to the same subscriber.
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+CREATE UNIQUE INDEX subscriptions_email_idx ON subscriptions (lower(email));
@@ -0,0 +1,171 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::SignedCookieJar;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{Sessions, SESSION_COOKIE_NAME},
+    utils::e500,
+};
+
+/// The id of the logged-in user, available as a request extension behind
+/// [`reject_anonymous_users`].
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::ops::Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Redirect requests without a valid session to `/login`.
+pub async fn reject_anonymous_users(
+    State(sessions): State<Sessions>,
+    jar: SignedCookieJar,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(session_cookie) = jar.get(SESSION_COOKIE_NAME) else {
+        return Redirect::to("/login").into_response();
+    };
+
+    match sessions.user_id(session_cookie.value()).await {
+        Ok(Some(user_id)) => {
+            request.extensions_mut().insert(UserId(user_id));
+            next.run(request).await
+        }
+        Ok(None) => Redirect::to("/login").into_response(),
+        Err(e) => e500(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        body::Body,
+        http::{header, HeaderValue, Request, StatusCode},
+        middleware,
+        response::IntoResponse,
+        routing::get,
+        Extension, Router,
+    };
+    use axum_extra::extract::{cookie::Key, SignedCookieJar};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::{reject_anonymous_users, UserId};
+    use crate::authentication::{MemorySessionStore, Sessions};
+
+    fn router(sessions: Sessions) -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|Extension(user_id): Extension<UserId>| async move { user_id.to_string() }),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                sessions,
+                reject_anonymous_users,
+            ))
+    }
+
+    /// The `Cookie` header of a browser holding a new session for `user_id`.
+    async fn session_cookie(sessions: &Sessions, key: Key, user_id: Uuid) -> HeaderValue {
+        let cookie = sessions.create(user_id, false).await.unwrap();
+        let response = SignedCookieJar::new(key).add(cookie).into_response();
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        set_cookie.split(';').next().unwrap().parse().unwrap()
+    }
+
+    async fn get_with(router: Router, cookie: Option<HeaderValue>) -> axum::response::Response {
+        let mut request = Request::builder().uri("/");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn assert_is_redirect_to_login(response: &axum::response::Response) {
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/login");
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_session_cookie_are_redirected_to_login() {
+        let sessions = Sessions::new(MemorySessionStore::default(), Key::generate());
+
+        let response = get_with(router(sessions), None).await;
+
+        assert_is_redirect_to_login(&response);
+    }
+
+    #[tokio::test]
+    async fn requests_with_an_unknown_session_are_redirected_to_login() {
+        let key = Key::generate();
+        let sessions = Sessions::new(MemorySessionStore::default(), key.clone());
+        // Signed with the right key, but created by another store.
+        let other_sessions = Sessions::new(MemorySessionStore::default(), key.clone());
+        let cookie = session_cookie(&other_sessions, key, Uuid::new_v4()).await;
+
+        let response = get_with(router(sessions), Some(cookie)).await;
+
+        assert_is_redirect_to_login(&response);
+    }
+
+    #[tokio::test]
+    async fn requests_with_a_cookie_signed_with_another_key_are_redirected_to_login() {
+        let sessions = Sessions::new(MemorySessionStore::default(), Key::generate());
+        let cookie = session_cookie(&sessions, Key::generate(), Uuid::new_v4()).await;
+
+        let response = get_with(router(sessions), Some(cookie)).await;
+
+        assert_is_redirect_to_login(&response);
+    }
+
+    #[tokio::test]
+    async fn requests_with_an_expired_session_are_redirected_to_login() {
+        let key = Key::generate();
+        let sessions = Sessions::new(
+            MemorySessionStore::expiring_after(Duration::from_millis(50)),
+            key.clone(),
+        );
+        let cookie = session_cookie(&sessions, key, Uuid::new_v4()).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = get_with(router(sessions), Some(cookie)).await;
+
+        assert_is_redirect_to_login(&response);
+    }
+
+    #[tokio::test]
+    async fn the_user_id_of_the_session_is_handed_to_the_route() {
+        let key = Key::generate();
+        let sessions = Sessions::new(MemorySessionStore::default(), key.clone());
+        let user_id = Uuid::new_v4();
+        let cookie = session_cookie(&sessions, key, user_id).await;
+
+        let response = get_with(router(sessions), Some(cookie)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, user_id.to_string());
+    }
+}
@@ -0,0 +1,7 @@
+mod middleware;
+mod password;
+mod session;
+
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{create_user, validate_credentials, AuthError, Credentials};
+pub use session::*;
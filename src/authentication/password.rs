@@ -0,0 +1,182 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::{PgPool, Row};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Verified against when the username does not exist, so that unknown and known
+/// usernames take the same amount of time to reject.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$\
+    CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, SecretString)>, anyhow::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let user_id: Uuid = row.try_get("user_id")?;
+    let password_hash: String = row.try_get("password_hash")?;
+    Ok(Some((user_id, SecretString::from(password_hash))))
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = SecretString::from(DUMMY_PASSWORD_HASH);
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task.")??;
+
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: SecretString,
+    password_candidate: SecretString,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+fn compute_password_hash(password: SecretString) -> Result<SecretString, anyhow::Error> {
+    let salt =
+        SaltString::encode_b64(&rand::random::<[u8; 16]>()).map_err(|e| anyhow::anyhow!(e))?;
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).map_err(|e| anyhow::anyhow!(e))?,
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)
+    .map_err(|e| anyhow::anyhow!(e))?
+    .to_string();
+    Ok(SecretString::from(password_hash))
+}
+
+/// Store a new user with an argon2id hash of `password`.
+#[tracing::instrument(name = "Create user", skip(password, pool))]
+pub async fn create_user(
+    username: &str,
+    password: SecretString,
+    pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+    let user_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO users (user_id, username, password_hash)
+        VALUES ($1, $2, $3)
+        "#,
+    )
+    .bind(user_id)
+    .bind(username)
+    .bind(password_hash.expose_secret())
+    .execute(pool)
+    .await
+    .context("Failed to store the new user in the database.")?;
+    Ok(user_id)
+}
+
+/// Hashing is CPU-bound: run it off the async runtime, inside the current span.
+fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_password_hash, verify_password_hash, AuthError};
+    use claims::{assert_err, assert_ok};
+    use secrecy::SecretString;
+
+    #[test]
+    fn a_hashed_password_is_verified_successfully() {
+        let hash = compute_password_hash(SecretString::from("correct horse")).unwrap();
+        assert_ok!(verify_password_hash(
+            hash,
+            SecretString::from("correct horse")
+        ));
+    }
+
+    #[test]
+    fn a_wrong_password_is_rejected_as_invalid_credentials() {
+        let hash = compute_password_hash(SecretString::from("correct horse")).unwrap();
+        let outcome = verify_password_hash(hash, SecretString::from("battery staple"));
+        assert!(matches!(
+            assert_err!(outcome),
+            AuthError::InvalidCredentials(_)
+        ));
+    }
+
+    #[test]
+    fn hashes_are_salted() {
+        let first = compute_password_hash(SecretString::from("password")).unwrap();
+        let second = compute_password_hash(SecretString::from("password")).unwrap();
+        use secrecy::ExposeSecret;
+        assert_ne!(first.expose_secret(), second.expose_secret());
+    }
+}
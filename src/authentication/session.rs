@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::extract::FromRef;
+use axum_extra::extract::cookie::{Cookie, Key, SameSite};
+use rand::distr::{Alphanumeric, SampleString};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha512};
+use uuid::Uuid;
+
+use crate::startup::HmacSecret;
+
+pub const SESSION_COOKIE_NAME: &str = "session_id";
+const SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Key used to sign the session cookie, derived from the application's HMAC secret.
+pub fn cookie_key(hmac_secret: &HmacSecret) -> Key {
+    let digest = Sha512::digest(hmac_secret.0.expose_secret().as_bytes());
+    Key::from(&digest[..])
+}
+
+/// Somewhere to keep track of which user each session belongs to.
+#[async_trait::async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    /// Remember that `session_id` belongs to `user_id`, and forget it after `ttl`.
+    async fn insert(
+        &self,
+        session_id: &str,
+        user_id: Uuid,
+        ttl: Duration,
+    ) -> Result<(), anyhow::Error>;
+
+    /// `None` if the session expired or never existed.
+    async fn get(&self, session_id: &str) -> Result<Option<Uuid>, anyhow::Error>;
+
+    async fn remove(&self, session_id: &str) -> Result<(), anyhow::Error>;
+}
+
+/// Sessions kept in Redis, which expires them for us.
+pub struct RedisSessionStore(ConnectionManager);
+
+impl RedisSessionStore {
+    pub fn new(redis: ConnectionManager) -> Self {
+        Self(redis)
+    }
+}
+
+fn redis_key(session_id: &str) -> String {
+    format!("session:{}", session_id)
+}
+
+#[async_trait::async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn insert(
+        &self,
+        session_id: &str,
+        user_id: Uuid,
+        ttl: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let _: () = self
+            .0
+            .clone()
+            .set_ex(redis_key(session_id), user_id.to_string(), ttl.as_secs())
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, session_id: &str) -> Result<Option<Uuid>, anyhow::Error> {
+        let user_id: Option<String> = self.0.clone().get(redis_key(session_id)).await?;
+        Ok(user_id.map(|user_id| user_id.parse()).transpose()?)
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<(), anyhow::Error> {
+        let _: () = self.0.clone().del(redis_key(session_id)).await?;
+        Ok(())
+    }
+}
+
+/// The session store, along with the key the session cookie is signed with.
+#[derive(Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    key: Key,
+}
+
+impl Sessions {
+    pub fn new(store: impl SessionStore, key: Key) -> Self {
+        Self {
+            store: Arc::new(store),
+            key,
+        }
+    }
+
+    /// Start a new session for `user_id` and return the cookie that identifies it.
+    #[tracing::instrument(name = "Create session", skip(self))]
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        secure: bool,
+    ) -> Result<Cookie<'static>, anyhow::Error> {
+        let session_id = Alphanumeric.sample_string(&mut rand::rng(), 32);
+        self.store
+            .insert(&session_id, user_id, SESSION_TTL)
+            .await
+            .context("Failed to store the session.")?;
+        Ok(Cookie::build((SESSION_COOKIE_NAME, session_id))
+            .path("/")
+            .http_only(true)
+            .secure(secure)
+            .same_site(SameSite::Lax)
+            .max_age(SESSION_TTL.try_into().expect("Session TTL out of range"))
+            .build())
+    }
+
+    /// Look up the user a session belongs to; `None` if it expired or never existed.
+    #[tracing::instrument(name = "Get session user", skip_all)]
+    pub async fn user_id(&self, session_id: &str) -> Result<Option<Uuid>, anyhow::Error> {
+        self.store
+            .get(session_id)
+            .await
+            .context("Failed to look up the session.")
+    }
+
+    #[tracing::instrument(name = "Delete session", skip_all)]
+    pub async fn delete(&self, session_id: &str) -> Result<(), anyhow::Error> {
+        self.store
+            .remove(session_id)
+            .await
+            .context("Failed to delete the session.")
+    }
+}
+
+impl FromRef<Sessions> for Key {
+    fn from_ref(sessions: &Sessions) -> Self {
+        sessions.key.clone()
+    }
+}
+
+/// Sessions kept in memory, for tests that have no Redis to talk to.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemorySessionStore {
+    sessions: std::sync::Mutex<std::collections::HashMap<String, (Uuid, std::time::Instant)>>,
+    /// Caps the lifetime of every session, so tests need not wait for it to run out.
+    max_ttl: Option<Duration>,
+}
+
+#[cfg(test)]
+impl MemorySessionStore {
+    pub(crate) fn expiring_after(max_ttl: Duration) -> Self {
+        Self {
+            max_ttl: Some(max_ttl),
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn insert(
+        &self,
+        session_id: &str,
+        user_id: Uuid,
+        ttl: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let ttl = self.max_ttl.map_or(ttl, |max_ttl| ttl.min(max_ttl));
+        self.sessions.lock().unwrap().insert(
+            session_id.to_string(),
+            (user_id, std::time::Instant::now() + ttl),
+        );
+        Ok(())
+    }
+
+    async fn get(&self, session_id: &str) -> Result<Option<Uuid>, anyhow::Error> {
+        Ok(self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .filter(|(_, expires_at)| *expires_at > std::time::Instant::now())
+            .map(|(user_id, _)| *user_id))
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<(), anyhow::Error> {
+        self.sessions.lock().unwrap().remove(session_id);
+        Ok(())
+    }
+}
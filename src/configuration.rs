@@ -1,44 +1,97 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+use redis::aio::ConnectionManager;
 use secrecy::{ExposeSecret, SecretString};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use tracing_subscriber::{filter::ParseError, EnvFilter};
+
+mod annotated;
+mod files;
+mod overrides;
+mod validation;
+
+pub use overrides::parse_override;
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{
+        CircuitBreaker, EmailClient, FileTransport, PostmarkTransport, RateLimiter, SmtpTls,
+        SmtpTransport,
+    },
+    routes::error_chain_fmt,
+};
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    #[serde(serialize_with = "redact")]
     pub redis_url: SecretString,
+    pub scheduler: SchedulerSettings,
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    #[serde(serialize_with = "redact")]
     pub hmac_secret: SecretString,
+    /// How long in-flight requests and jobs get to finish after SIGINT or SIGTERM.
+    pub shutdown_timeout_milliseconds: u64,
+    /// Where email templates are read from, relative to the working directory.
+    pub templates_directory: PathBuf,
+    /// How long unsubscribe links in newsletter issues stay valid. They never expire if unset.
+    #[serde(default)]
+    pub unsubscribe_link_ttl_days: Option<u32>,
+    /// `tracing` filter directives, e.g. `info,sqlx=warn`. `RUST_LOG` is used if unset.
+    #[serde(default)]
+    pub log_filter: Option<String>,
 }
 
-#[derive(serde::Deserialize, Clone)]
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.shutdown_timeout_milliseconds)
+    }
+
+    pub fn unsubscribe_link_ttl(&self) -> Option<chrono::Duration> {
+        self.unsubscribe_link_ttl_days
+            .map(|days| chrono::Duration::days(days.into()))
+    }
+
+    pub fn log_filter(&self) -> Result<EnvFilter, ParseError> {
+        match &self.log_filter {
+            Some(directives) => EnvFilter::try_new(directives),
+            None => Ok(EnvFilter::from_default_env()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct DatabaseSettings {
-    pub usename: String,
+    pub username: String,
+    #[serde(serialize_with = "redact")]
     pub password: SecretString,
     pub port: u16,
     pub host: String,
     pub database_name: String,
-    pub requrire_ssl: bool,
+    pub require_ssl: bool,
+    /// Apply pending migrations when the application starts.
+    pub migrate_on_startup: bool,
 }
 
 impl DatabaseSettings {
     pub fn connect_options(&self) -> PgConnectOptions {
-        let ssl_mode = if self.requrire_ssl {
+        let ssl_mode = if self.require_ssl {
             PgSslMode::Require
         } else {
             PgSslMode::Prefer
         };
         PgConnectOptions::new()
             .host(&self.host)
-            .username(&self.usename)
+            .username(&self.username)
             .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
@@ -46,25 +99,156 @@ impl DatabaseSettings {
     }
 }
 
-#[derive(serde::Deserialize, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct EmailClientSettings {
+    /// Which backend delivers the emails.
+    #[serde(default)]
+    pub kind: EmailTransportKind,
+    /// Postmark API endpoint, used when `kind` is `postmark`.
     pub base_url: String,
     pub sender_email: String,
+    /// Postmark server token, used when `kind` is `postmark`.
+    #[serde(serialize_with = "redact")]
     pub authorization_token: SecretString,
     pub timeout_milliseconds: u64,
+    /// Required when `kind` is `smtp`.
+    #[serde(default)]
+    pub smtp: Option<SmtpSettings>,
+    /// Required when `kind` is `file`.
+    #[serde(default)]
+    pub file: Option<FileSinkSettings>,
+    /// No client-side rate limiting if unset.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitSettings>,
+    /// No circuit breaker if unset.
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerSettings>,
+    /// Every call to `POST /webhooks/postmark` is rejected if unset.
+    #[serde(default)]
+    pub postmark_webhook: Option<PostmarkWebhookSettings>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransportKind {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    #[serde(serialize_with = "redact_option")]
+    pub password: Option<SecretString>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct FileSinkSettings {
+    /// mbox file the emails are appended to. Created if missing.
+    pub path: PathBuf,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct RateLimitSettings {
+    /// Sustained rate, shared by every replica.
+    pub requests_per_second: f64,
+    /// How many requests may be sent at once after a quiet period.
+    pub burst: u32,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive failures after which the provider stops being contacted.
+    pub failure_threshold: u32,
+    /// How long to wait before letting a probe request through.
+    pub cooldown_milliseconds: u64,
+}
+
+/// How Postmark authenticates its webhook calls. Either method is accepted.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct PostmarkWebhookSettings {
+    /// Basic auth credentials, as embedded in the webhook URL set on Postmark.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    #[serde(serialize_with = "redact_option")]
+    pub password: Option<SecretString>,
+    /// Expected in the `X-Webhook-Secret` header, set as a custom header on Postmark.
+    #[serde(default)]
+    #[serde(serialize_with = "redact_option")]
+    pub secret: Option<SecretString>,
 }
 
 impl EmailClientSettings {
-    pub fn client(self) -> EmailClient {
-        let sender_email = self.sender().expect("Invalid sender email address.");
+    /// Build the client for the configured transport.
+    ///
+    /// `redis` lets replicas share the rate limit; without it each process gets the
+    /// whole budget.
+    pub fn client(self, redis: Option<ConnectionManager>) -> Result<EmailClient, anyhow::Error> {
+        let mut client = self.transport_client()?;
+        if let Some(rate_limit) = self.rate_limit {
+            client = client.with_rate_limiter(RateLimiter::new(
+                rate_limit.requests_per_second,
+                rate_limit.burst,
+                redis,
+            )?);
+        }
+        if let Some(circuit_breaker) = self.circuit_breaker {
+            client = client.with_circuit_breaker(CircuitBreaker::new(
+                circuit_breaker.failure_threshold,
+                std::time::Duration::from_millis(circuit_breaker.cooldown_milliseconds),
+            ));
+        }
+        Ok(client)
+    }
+
+    fn transport_client(&self) -> Result<EmailClient, anyhow::Error> {
+        let sender_email = self
+            .sender()
+            .map_err(|e| anyhow::anyhow!("Invalid sender email address: {}", e))?;
         let timeout = self.timeout();
 
-        EmailClient::new(
-            self.base_url,
-            sender_email,
-            self.authorization_token,
-            timeout,
-        )
+        let client = match self.kind {
+            EmailTransportKind::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkTransport::new(
+                    self.base_url.clone(),
+                    self.authorization_token.clone(),
+                    timeout,
+                )
+                .context("Failed to set up the Postmark transport.")?,
+            ),
+            EmailTransportKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .clone()
+                    .context("`email_client.smtp` is required when `kind` is `smtp`.")?;
+                let credentials = smtp.username.map(|username| {
+                    let password = smtp.password.unwrap_or_else(|| SecretString::from(""));
+                    (username, password)
+                });
+                let transport =
+                    SmtpTransport::new(&smtp.host, smtp.port, smtp.tls, credentials, timeout)
+                        .context("Failed to set up the SMTP transport.")?;
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportKind::File => {
+                let file = self
+                    .file
+                    .clone()
+                    .context("`email_client.file` is required when `kind` is `file`.")?;
+                EmailClient::new(sender_email, FileTransport::new(file.path))
+            }
+        };
+        Ok(client)
     }
 
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
@@ -76,14 +260,199 @@ impl EmailClientSettings {
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let configuration_directory = base_path.join("configuration");
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SchedulerSettings {
+    pub jobs: Vec<ScheduledJobSettings>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ScheduledJobSettings {
+    /// Unique name, used for the Redis lock and to remember the last tick.
+    pub name: String,
+    /// Cron expression, seconds first: `sec min hour day-of-month month day-of-week [year]`.
+    pub schedule: String,
+    pub job_type: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+    pub catch_up: CatchUpPolicy,
+}
+
+impl ScheduledJobSettings {
+    pub fn schedule(&self) -> Result<cron::Schedule, cron::error::Error> {
+        self.schedule.parse()
+    }
+}
+
+/// What to do with ticks that were missed while no scheduler was running.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUpPolicy {
+    /// Drop missed ticks and wait for the next one.
+    Skip,
+    /// Enqueue a single job for all the missed ticks.
+    RunOnce,
+    /// Enqueue a job for every missed tick.
+    RunAll,
+}
+
+/// Shown in place of secrets by [`print_configuration`].
+const REDACTED: &str = "[REDACTED]";
+
+fn redact<S: serde::Serializer>(_: &SecretString, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(REDACTED)
+}
+
+fn redact_option<S: serde::Serializer>(
+    secret: &Option<SecretString>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(secret) => redact(secret, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfigurationError {
+    #[error("{}", invalid_configuration_report(.0))]
+    Invalid(Vec<String>),
+    #[error("Failed to read the configuration")]
+    Unreadable(#[from] config::ConfigError),
+    #[error("{0}")]
+    UnknownEnvironment(String),
+    #[error("Failed to determine the current directory")]
+    CurrentDirectory(#[source] std::io::Error),
+}
+
+impl std::fmt::Debug for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+fn invalid_configuration_report(problems: &[String]) -> String {
+    let mut report = format!("The configuration has {} problem(s):", problems.len());
+    for problem in problems {
+        report.push_str("\n  - ");
+        report.push_str(problem);
+    }
+    report
+}
+
+/// Where the configuration is read from, and what overrides it.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigurationOptions {
+    /// Holds the YAML files. `configuration/` under the working directory if unset.
+    pub directory: Option<PathBuf>,
+    /// Picks `{environment}.yaml`. Read from `APP_ENVIRONMENT` if unset, `local` by
+    /// default.
+    pub environment: Option<Environment>,
+    /// `key=value` pairs that win over every other layer.
+    pub overrides: Vec<(String, String)>,
+}
+
+impl ConfigurationOptions {
+    pub fn directory(&self) -> Result<PathBuf, ConfigurationError> {
+        match &self.directory {
+            Some(directory) => Ok(directory.clone()),
+            None => Ok(std::env::current_dir()
+                .map_err(ConfigurationError::CurrentDirectory)?
+                .join("configuration")),
+        }
+    }
+
+    pub fn environment(&self) -> Result<Environment, ConfigurationError> {
+        match &self.environment {
+            Some(environment) => Ok(*environment),
+            None => std::env::var("APP_ENVIRONMENT")
+                .unwrap_or_else(|_| "local".into())
+                .try_into()
+                .map_err(ConfigurationError::UnknownEnvironment),
+        }
+    }
+}
+
+pub fn get_configuration(options: &ConfigurationOptions) -> Result<Settings, ConfigurationError> {
+    validate(layers(options)?)
+}
+
+/// [`Settings`], along with the merged configuration they were read from.
+pub struct LoadedConfiguration {
+    pub settings: Settings,
+    merged: config::Value,
+}
+
+impl LoadedConfiguration {
+    /// Keys whose value differs in `other`, including keys set in only one of them.
+    pub fn changed_keys(&self, other: &LoadedConfiguration) -> Vec<String> {
+        let (mut before, mut after) = (BTreeMap::new(), BTreeMap::new());
+        flatten(&self.merged, String::new(), &mut before);
+        flatten(&other.merged, String::new(), &mut after);
+        let mut keys: Vec<_> = before
+            .iter()
+            .filter(|(key, value)| after.get(*key) != Some(*value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.extend(
+            after
+                .keys()
+                .filter(|key| !before.contains_key(*key))
+                .cloned(),
+        );
+        keys.sort();
+        keys
+    }
+}
+
+/// Like [`get_configuration`], keeping what is needed to tell what changed between two
+/// loads.
+pub fn load_configuration(
+    options: &ConfigurationOptions,
+) -> Result<LoadedConfiguration, ConfigurationError> {
+    let (settings, merged) = resolve(layers(options)?)?;
+    Ok(LoadedConfiguration { settings, merged })
+}
+
+fn flatten(value: &config::Value, path: String, leaves: &mut BTreeMap<String, String>) {
+    match &value.kind {
+        config::ValueKind::Table(table) => {
+            for (key, value) in table {
+                flatten(value, validation::join(&path, key), leaves);
+            }
+        }
+        config::ValueKind::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                flatten(value, format!("{}[{}]", path, i), leaves);
+            }
+        }
+        _ => {
+            leaves.insert(path, value.to_string());
+        }
+    }
+}
+
+/// What `config print` shows.
+pub struct PrintedConfiguration {
+    /// The configuration as YAML, with secrets redacted.
+    pub yaml: String,
+    /// Why the configuration is invalid, if it is. `yaml` then shows the merged layers
+    /// as they are, rather than the settings with their defaults.
+    pub error: Option<ConfigurationError>,
+}
+
+/// The effective configuration as YAML, with secrets redacted and every value annotated
+/// with the file, environment variable or `--set` override it comes from.
+pub fn print_configuration(
+    options: &ConfigurationOptions,
+) -> Result<PrintedConfiguration, ConfigurationError> {
+    describe(layers(options)?)
+}
 
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
+/// `base.yaml`, then `{environment}.yaml`, then `APP_` environment variables, then
+/// `--set` overrides.
+fn layers(options: &ConfigurationOptions) -> Result<config::Config, ConfigurationError> {
+    let configuration_directory = options.directory()?;
+    let environment = options.environment()?;
 
     let environment_filename = format!("{}.yaml", environment.as_str());
     let settings = config::Config::builder()
@@ -96,15 +465,115 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .add_source(
             config::Environment::with_prefix("APP")
                 .prefix_separator("_")
-                .separator("__"),
+                .separator("__")
+                .source(Some(settings_variables())),
         )
+        .add_source(overrides::Overrides(options.overrides.clone()))
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    Ok(settings)
 }
 
+/// `APP_` variables, minus `APP_ENVIRONMENT`: it picks the files to read, it is not a
+/// setting.
+fn settings_variables() -> config::Map<String, String> {
+    std::env::vars()
+        .filter(|(name, _)| name != "APP_ENVIRONMENT")
+        .collect()
+}
+
+fn describe(settings: config::Config) -> Result<PrintedConfiguration, ConfigurationError> {
+    let mut problems = Vec::new();
+    let merged = merge(settings, &mut problems)?;
+    let (shown, error) = match deserialize(&merged, problems) {
+        Ok(settings) => (
+            serde_json::to_value(&settings).expect("Settings always serialize to JSON"),
+            None,
+        ),
+        Err(e) => {
+            let mut layers = merged
+                .clone()
+                .try_deserialize::<serde_json::Value>()
+                .expect("Configuration values always deserialize to JSON");
+            redact_secrets(&mut layers);
+            (layers, Some(e))
+        }
+    };
+    Ok(PrintedConfiguration {
+        yaml: annotated::render(&shown, &merged),
+        error,
+    })
+}
+
+/// Redact every value whose key is the name of a secret, wherever it is: a secret under
+/// a misspelled section must not be shown either.
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields {
+                let is_secret = files::SECRETS
+                    .iter()
+                    .any(|path| path.rsplit('.').next() == Some(key.as_str()));
+                if is_secret && !value.is_null() {
+                    *value = serde_json::Value::from(REDACTED);
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+fn validate(settings: config::Config) -> Result<Settings, ConfigurationError> {
+    resolve(settings).map(|(settings, _)| settings)
+}
+
+/// Deserialize the merged configuration, reporting every problem found rather than the
+/// first one.
+///
+/// Also returns the merged configuration, with file references replaced by the content
+/// of the files.
+fn resolve(settings: config::Config) -> Result<(Settings, config::Value), ConfigurationError> {
+    let mut problems = Vec::new();
+    let merged = merge(settings, &mut problems)?;
+    let settings = deserialize(&merged, problems)?;
+    Ok((settings, merged))
+}
+
+/// Merge the layers and read the secrets referenced as files.
+fn merge(
+    settings: config::Config,
+    problems: &mut Vec<String>,
+) -> Result<config::Value, ConfigurationError> {
+    let mut merged = config::Value::new(None, config::Source::collect(&settings)?);
+    files::read_file_references(&mut merged, problems);
+    Ok(merged)
+}
+
+/// `problems` are those found while merging, reported along with the others.
+fn deserialize(
+    merged: &config::Value,
+    mut problems: Vec<String>,
+) -> Result<Settings, ConfigurationError> {
+    let settings = validation::deserialize::<Settings>(
+        merged.clone(),
+        validation::SETTINGS_KEYS,
+        &mut problems,
+    );
+    validation::check_values(merged, &mut problems);
+    match settings {
+        Some(settings) if problems.is_empty() => Ok(settings),
+        _ => Err(ConfigurationError::Invalid(problems)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Environment {
     Local,
+    Test,
+    Staging,
     Production,
 }
 
@@ -112,6 +581,8 @@ impl Environment {
     pub fn as_str(&self) -> &'static str {
         match self {
             Environment::Local => "local",
+            Environment::Test => "test",
+            Environment::Staging => "staging",
             Environment::Production => "production",
         }
     }
@@ -123,8 +594,294 @@ impl TryFrom<String> for Environment {
     fn try_from(value: String) -> Result<Self, Self::Error> {
         match value.to_lowercase().as_str() {
             "local" => Ok(Self::Local),
+            "test" => Ok(Self::Test),
+            "staging" => Ok(Self::Staging),
             "production" => Ok(Self::Production),
-            other => Err(format!("{} is not supported", other)),
+            other => Err(format!(
+                "{} is not a supported environment. Use `local`, `test`, `staging` or `production`.",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        describe, resolve, validate, validation, ConfigurationError, EmailTransportKind,
+        Environment, LoadedConfiguration,
+    };
+    use claims::{assert_err, assert_ok};
+    use config::{Config, File, FileFormat};
+
+    fn layers(files: &[&str]) -> Config {
+        let builder = files.iter().fold(Config::builder(), |builder, yaml| {
+            builder.add_source(File::from_str(yaml, FileFormat::Yaml))
+        });
+        builder.build().unwrap()
+    }
+
+    fn load(files: &[&str]) -> Result<super::Settings, ConfigurationError> {
+        validate(layers(files))
+    }
+
+    fn loaded(files: &[&str]) -> LoadedConfiguration {
+        let (settings, merged) = assert_ok!(resolve(layers(files)));
+        LoadedConfiguration { settings, merged }
+    }
+
+    #[test]
+    fn the_shipped_configuration_files_are_valid() {
+        let base = include_str!("configuration/base.yaml");
+        assert_ok!(load(&[base, include_str!("configuration/local.yaml")]));
+        assert_ok!(load(&[base, include_str!("configuration/production.yaml")]));
+        assert_ok!(load(&[base, include_str!("configuration/staging.yaml")]));
+        assert_ok!(load(&[base, include_str!("configuration/test.yaml")]));
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let overrides = r#"
+application:
+    port: 0
+database:
+    usename: "postgres"
+email_client:
+    sender_email: "not-an-email"
+"#;
+        let Err(ConfigurationError::Invalid(problems)) =
+            load(&[include_str!("configuration/base.yaml"), overrides])
+        else {
+            panic!("Expected an invalid configuration");
+        };
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert_eq!(
+            problems[0],
+            "unknown key `database.usename`, did you mean `database.username`?"
+        );
+        assert_eq!(problems[1], "`application.port` must not be 0");
+        assert!(problems[2].starts_with("`email_client.sender_email`"));
+    }
+
+    #[test]
+    fn missing_keys_are_reported_along_with_the_other_problems() {
+        let base = include_str!("configuration/base.yaml")
+            .replace("    database_name: \"newsletter\"\n", "")
+            .replace("redis_url: \"redis://127.0.0.1:6379\"\n", "");
+        let Err(ConfigurationError::Invalid(problems)) =
+            load(&[&base, "application:\n    port: 0\n"])
+        else {
+            panic!("Expected an invalid configuration");
+        };
+        assert_eq!(
+            problems,
+            vec![
+                "missing key `database.database_name`",
+                "missing key `redis_url`",
+                "`application.port` must not be 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn type_errors_name_the_offending_key() {
+        let overrides = "email_client:\n    timeout_milliseconds: \"soon\"\n";
+        let Err(ConfigurationError::Invalid(problems)) =
+            load(&[include_str!("configuration/base.yaml"), overrides])
+        else {
+            panic!("Expected an invalid configuration");
+        };
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(
+            problems[0].ends_with("for key `email_client.timeout_milliseconds`"),
+            "{}",
+            problems[0]
+        );
+    }
+
+    #[test]
+    fn an_unusable_email_transport_is_an_error() {
+        let mut settings = loaded(&[include_str!("configuration/base.yaml")]).settings;
+        settings.email_client.kind = EmailTransportKind::Smtp;
+        settings.email_client.smtp = None;
+
+        assert!(settings.email_client.client(None).is_err());
+    }
+
+    #[test]
+    fn an_invalid_configuration_is_printed_with_its_problems() {
+        let overrides = "application:\n    port: 0\ndatabse:\n    password: hunter2\n";
+
+        let printed = assert_ok!(describe(layers(&[
+            include_str!("configuration/base.yaml"),
+            overrides
+        ])));
+
+        assert!(printed.yaml.contains("port: 0"), "{}", printed.yaml);
+        assert!(!printed.yaml.contains("hunter2"), "{}", printed.yaml);
+        let Some(ConfigurationError::Invalid(problems)) = printed.error else {
+            panic!("Expected an invalid configuration");
+        };
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+    }
+
+    #[test]
+    fn every_setting_is_a_known_key() {
+        let every_section = r#"
+application:
+    unsubscribe_link_ttl_days: 30
+    log_filter: info
+email_client:
+    smtp:
+        host: smtp.example.com
+        port: 587
+        username: newsletter
+        password: password
+    file:
+        path: emails.mbox
+    postmark_webhook:
+        username: postmark
+        password: password
+        secret: secret
+"#;
+        let settings = loaded(&[include_str!("configuration/base.yaml"), every_section]).settings;
+        let mut keys = Vec::new();
+        json_keys(&serde_json::to_value(&settings).unwrap(), "", &mut keys);
+        keys.sort();
+
+        let leaves: Vec<_> = validation::SETTINGS_KEYS
+            .iter()
+            .map(|key| key.path)
+            .filter(|path| !is_section(path))
+            .collect();
+        assert_eq!(keys, leaves);
+    }
+
+    #[test]
+    fn exactly_the_keys_without_a_default_are_required() {
+        // Every optional section, so that their keys can be left out too.
+        let every_section = r#"
+email_client:
+    smtp: { host: smtp.example.com, port: 587 }
+    file: { path: emails.mbox }
+    postmark_webhook: { secret: secret }
+"#;
+        let config = config::Config::builder()
+            .add_source(config::File::from_str(
+                include_str!("configuration/base.yaml"),
+                config::FileFormat::Yaml,
+            ))
+            .add_source(config::File::from_str(
+                every_section,
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap();
+        let merged = config::Value::new(None, config::Source::collect(&config).unwrap());
+
+        for key in validation::SETTINGS_KEYS {
+            let mut value = merged.clone();
+            let segments: Vec<_> = key.path.split('.').collect();
+            let (field, parents) = segments.split_last().unwrap();
+            merged_table(&mut value, parents).remove(*field);
+            let mut problems = Vec::new();
+            let settings = validation::deserialize::<super::Settings>(
+                value,
+                validation::SETTINGS_KEYS,
+                &mut problems,
+            );
+            assert_eq!(
+                settings.is_none(),
+                key.required,
+                "{}: {:?}",
+                key.path,
+                problems
+            );
+        }
+    }
+
+    /// The table at `path` in `value`, the first element standing for `[]`.
+    fn merged_table<'a>(
+        value: &'a mut config::Value,
+        path: &[&str],
+    ) -> &'a mut config::Map<String, config::Value> {
+        let mut value = value;
+        for key in path {
+            let config::ValueKind::Table(table) = &mut value.kind else {
+                panic!("`{}` is not a table", key);
+            };
+            value = match key.strip_suffix("[]") {
+                Some(list) => match &mut table.get_mut(list).unwrap().kind {
+                    config::ValueKind::Array(elements) => &mut elements[0],
+                    _ => panic!("`{}` is not a list", list),
+                },
+                None => table.get_mut(*key).unwrap(),
+            };
+        }
+        match &mut value.kind {
+            config::ValueKind::Table(table) => table,
+            _ => panic!("not a table"),
+        }
+    }
+
+    fn is_section(path: &str) -> bool {
+        validation::SETTINGS_KEYS.iter().any(|key| {
+            key.path.starts_with(&format!("{}.", path))
+                || key.path.starts_with(&format!("{}[]", path))
+        })
+    }
+
+    /// Leaf keys of `value`, with `[]` standing for list elements. Job payloads are
+    /// free-form and count as a single key.
+    fn json_keys(value: &serde_json::Value, path: &str, keys: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(fields) if !path.ends_with(".payload") => {
+                for (key, value) in fields {
+                    json_keys(value, &validation::join(path, key), keys);
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for value in values.iter().take(1) {
+                    json_keys(value, &format!("{}[]", path), keys);
+                }
+            }
+            _ => keys.push(path.to_string()),
+        }
+    }
+
+    #[test]
+    fn changed_keys_are_listed() {
+        let base = include_str!("configuration/base.yaml");
+        let before = loaded(&[base]);
+        let after = loaded(&[
+            base,
+            "application:\n    port: 8080\n    log_filter: debug\nemail_client:\n    timeout_milliseconds: 5000\n",
+        ]);
+
+        assert_eq!(
+            before.changed_keys(&after),
+            vec![
+                "application.log_filter",
+                "application.port",
+                "email_client.timeout_milliseconds"
+            ]
+        );
+        assert!(before.changed_keys(&loaded(&[base])).is_empty());
+    }
+
+    #[test]
+    fn environments_are_parsed_case_insensitively() {
+        for (name, environment) in [
+            ("local", Environment::Local),
+            ("Test", Environment::Test),
+            ("STAGING", Environment::Staging),
+            ("production", Environment::Production),
+        ] {
+            assert_eq!(
+                assert_ok!(Environment::try_from(name.to_string())),
+                environment
+            );
         }
+        assert_err!(Environment::try_from("qa".to_string()));
     }
 }
@@ -0,0 +1,144 @@
+use config::{Value, ValueKind};
+
+/// Origin `config` gives to values read from environment variables.
+const ENVIRONMENT_ORIGIN: &str = "the environment";
+
+/// Render `settings` as YAML, each value followed by a comment naming where it was set.
+///
+/// `merged` is the configuration `settings` was deserialized from; values that are not in
+/// it come from a default.
+pub(crate) fn render(settings: &serde_json::Value, merged: &Value) -> String {
+    let mut out = String::new();
+    if let serde_json::Value::Object(fields) = settings {
+        render_fields(fields, Some(merged), &mut Vec::new(), 0, &mut out);
+    }
+    out
+}
+
+fn render_fields<'a>(
+    fields: &'a serde_json::Map<String, serde_json::Value>,
+    node: Option<&Value>,
+    path: &mut Vec<&'a str>,
+    depth: usize,
+    out: &mut String,
+) {
+    for (key, value) in fields {
+        let node = node.and_then(|node| match &node.kind {
+            ValueKind::Table(table) => table.get(key),
+            _ => None,
+        });
+        path.push(key);
+        indent(depth, out);
+        out.push_str(key);
+        out.push(':');
+        render_value(value, node, path, depth, out);
+        path.pop();
+    }
+}
+
+fn render_value<'a>(
+    value: &'a serde_json::Value,
+    node: Option<&Value>,
+    path: &mut Vec<&'a str>,
+    depth: usize,
+    out: &mut String,
+) {
+    match value {
+        serde_json::Value::Object(fields) if !fields.is_empty() => {
+            out.push('\n');
+            render_fields(fields, node, path, depth + 1, out);
+        }
+        serde_json::Value::Array(elements) if !elements.is_empty() => {
+            out.push('\n');
+            for (i, element) in elements.iter().enumerate() {
+                let node = node.and_then(|node| match &node.kind {
+                    ValueKind::Array(array) => array.get(i),
+                    _ => None,
+                });
+                indent(depth + 1, out);
+                out.push('-');
+                render_value(element, node, path, depth + 1, out);
+            }
+        }
+        scalar => {
+            out.push(' ');
+            out.push_str(&scalar.to_string());
+            out.push_str("  # ");
+            out.push_str(&source(node, path));
+            out.push('\n');
+        }
+    }
+}
+
+fn indent(depth: usize, out: &mut String) {
+    out.push_str(&"    ".repeat(depth));
+}
+
+fn source(node: Option<&Value>, path: &[&str]) -> String {
+    match node.and_then(|node| node.origin()) {
+        Some(ENVIRONMENT_ORIGIN) => format!("APP_{}", path.join("__").to_uppercase()),
+        Some(origin) => origin.to_string(),
+        None => "default".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use config::{Map, Value, ValueKind};
+
+    fn value(origin: &str, kind: impl Into<ValueKind>) -> Value {
+        Value::new(Some(&origin.to_string()), kind)
+    }
+
+    #[test]
+    fn values_are_annotated_with_their_source() {
+        let database = Map::from([
+            (
+                "host".to_string(),
+                value("configuration/base.yaml", "127.0.0.1"),
+            ),
+            ("port".to_string(), value("the environment", "5433")),
+        ]);
+        let merged = Value::new(
+            None,
+            Map::from([("database".to_string(), Value::new(None, database))]),
+        );
+        let settings = serde_json::json!({
+            "database": { "host": "127.0.0.1", "port": 5433, "require_ssl": false }
+        });
+
+        let rendered = render(&settings, &merged);
+
+        assert_eq!(
+            rendered,
+            "database:\n    \
+                 host: \"127.0.0.1\"  # configuration/base.yaml\n    \
+                 port: 5433  # APP_DATABASE__PORT\n    \
+                 require_ssl: false  # default\n"
+        );
+    }
+
+    #[test]
+    fn list_elements_are_annotated() {
+        let job = Map::from([(
+            "name".to_string(),
+            value("configuration/base.yaml", "cleanup"),
+        )]);
+        let merged = Value::new(
+            None,
+            Map::from([(
+                "jobs".to_string(),
+                Value::new(None, vec![Value::new(None, job)]),
+            )]),
+        );
+        let settings = serde_json::json!({ "jobs": [{ "name": "cleanup" }], "tags": [] });
+
+        let rendered = render(&settings, &merged);
+
+        assert_eq!(
+            rendered,
+            "jobs:\n    -\n        name: \"cleanup\"  # configuration/base.yaml\ntags: []  # default\n"
+        );
+    }
+}
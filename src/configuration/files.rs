@@ -0,0 +1,210 @@
+use config::{Map, Value, ValueKind};
+
+/// Settings that can be read from a file: the secrets, and nothing else, so that e.g. a
+/// `file` key in a job payload is left alone.
+pub(crate) const SECRETS: &[&str] = &[
+    "application.hmac_secret",
+    "database.password",
+    "email_client.authorization_token",
+    "email_client.smtp.password",
+    "email_client.postmark_webhook.password",
+    "email_client.postmark_webhook.secret",
+    "redis_url",
+];
+
+/// Suffix of a key naming the file its value is read from, as set by e.g.
+/// `APP_DATABASE__PASSWORD_FILE`.
+const FILE_SUFFIX: &str = "_file";
+
+/// Only key of a mapping naming the file a value is read from, e.g.
+/// `password: { file: "/run/secrets/db_password" }`.
+const FILE_KEY: &str = "file";
+
+/// Replace references to files with the content of the files, so that secrets can be
+/// mounted rather than written in the configuration.
+///
+/// A secret is read from a file if it is a mapping with a single `file` key, or if a
+/// sibling key with the `_file` suffix is set; the latter wins. The file becomes the
+/// origin of the value. Trailing newlines are stripped.
+pub(crate) fn read_file_references(value: &mut Value, problems: &mut Vec<String>) {
+    for path in SECRETS {
+        let (parent, name) = match path.rsplit_once('.') {
+            Some((parent, name)) => (Some(parent), name),
+            None => (None, *path),
+        };
+        let Some(table) = table_at(value, parent) else {
+            continue;
+        };
+        let reference = table
+            .remove(&format!("{}{}", name, FILE_SUFFIX))
+            .or_else(|| table.get(name).and_then(file_reference));
+        let Some(reference) = reference else {
+            continue;
+        };
+        let value = read(&reference).unwrap_or_else(|e| {
+            problems.push(format!("`{}`: {}", path, e));
+            // Not reported as missing on top of that.
+            Value::new(None, "")
+        });
+        table.insert(name.to_string(), value);
+    }
+}
+
+/// The table at the dotted `path`, if every section along it is set.
+fn table_at<'a>(value: &'a mut Value, path: Option<&str>) -> Option<&'a mut Map<String, Value>> {
+    let mut table = match &mut value.kind {
+        ValueKind::Table(table) => table,
+        _ => return None,
+    };
+    for key in path.into_iter().flat_map(|path| path.split('.')) {
+        table = match &mut table.get_mut(key)?.kind {
+            ValueKind::Table(nested) => nested,
+            _ => return None,
+        };
+    }
+    Some(table)
+}
+
+fn file_reference(value: &Value) -> Option<Value> {
+    match &value.kind {
+        ValueKind::Table(table) if table.len() == 1 => table.get(FILE_KEY).cloned(),
+        _ => None,
+    }
+}
+
+fn read(reference: &Value) -> Result<Value, String> {
+    let path = reference
+        .clone()
+        .into_string()
+        .map_err(|e| format!("invalid file reference: {}", e))?;
+    let content =
+        std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    Ok(Value::new(
+        Some(&path),
+        content.trim_end_matches(['\r', '\n']),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_file_references;
+    use config::{Config, File, FileFormat, Map, Value, ValueKind};
+    use std::path::PathBuf;
+
+    /// A file under the system temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{}-{}", uuid::Uuid::new_v4(), name));
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn resolve(yaml: &str, variables: &[(&str, &str)]) -> (Value, Vec<String>) {
+        let variables: Map<String, String> = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let config = Config::builder()
+            .add_source(File::from_str(yaml, FileFormat::Yaml))
+            .add_source(
+                config::Environment::with_prefix("APP")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .source(Some(variables)),
+            )
+            .build()
+            .unwrap();
+        let mut value = Value::new(None, config::Source::collect(&config).unwrap());
+        let mut problems = Vec::new();
+        read_file_references(&mut value, &mut problems);
+        (value, problems)
+    }
+
+    fn get<'a>(value: &'a Value, path: &[&str]) -> &'a Value {
+        path.iter().fold(value, |value, key| match &value.kind {
+            ValueKind::Table(table) => &table[*key],
+            _ => panic!("{} is not a table", key),
+        })
+    }
+
+    #[test]
+    fn values_are_read_from_files_referenced_in_the_yaml() {
+        let password = TempFile::new("db_password", "hunter2\n");
+        let yaml = format!(
+            "database:\n    username: postgres\n    password:\n        file: {}\nredis_url: redis://127.0.0.1:6379\n",
+            password.path()
+        );
+
+        let (value, problems) = resolve(&yaml, &[]);
+
+        assert!(problems.is_empty(), "{:?}", problems);
+        let password_value = get(&value, &["database", "password"]);
+        assert_eq!(password_value.clone().into_string().unwrap(), "hunter2");
+        assert_eq!(password_value.origin(), Some(password.path()));
+    }
+
+    #[test]
+    fn file_environment_variables_win_over_the_yaml() {
+        let redis_url = TempFile::new("redis_url", "redis://redis:6379");
+        let yaml = "database:\n    username: postgres\n    password: password\nredis_url: redis://127.0.0.1:6379\n";
+
+        let (value, problems) = resolve(yaml, &[("APP_REDIS_URL_FILE", redis_url.path())]);
+
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(
+            get(&value, &["redis_url"]).clone().into_string().unwrap(),
+            "redis://redis:6379"
+        );
+        let ValueKind::Table(table) = &value.kind else {
+            panic!("Expected a table");
+        };
+        assert!(!table.contains_key("redis_url_file"));
+    }
+
+    #[test]
+    fn unreadable_files_are_reported() {
+        let yaml = "database:\n    username: postgres\n    password:\n        file: /nonexistent/db_password\nredis_url: redis://127.0.0.1:6379\n";
+
+        let (_, problems) = resolve(yaml, &[]);
+
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(
+            problems[0].starts_with("`database.password`: failed to read /nonexistent/db_password")
+        );
+    }
+
+    #[test]
+    fn only_secrets_are_read_from_files() {
+        let username = TempFile::new("db_username", "admin");
+        let yaml = format!(
+            "database:\n    username:\n        file: {}\n    password: password\nscheduler:\n    jobs:\n        - payload:\n              file: report.csv\n",
+            username.path()
+        );
+
+        let (value, problems) = resolve(&yaml, &[("APP_DATABASE__PORT_FILE", username.path())]);
+
+        assert!(problems.is_empty(), "{:?}", problems);
+        let database = get(&value, &["database"]);
+        assert!(matches!(
+            get(database, &["username"]).kind,
+            ValueKind::Table(_)
+        ));
+        assert_eq!(
+            get(database, &["port_file"]).clone().into_string().unwrap(),
+            username.path()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use config::{ConfigError, Map, Source, Value, ValueKind};
+
+/// Origin of the values set with `--set`, as shown by `config print`.
+const ORIGIN: &str = "--set";
+
+/// `key=value` pairs given on the command line, the layer with the highest priority.
+///
+/// Keys are dotted paths such as `application.port`. Values are strings, converted to
+/// the type of the setting like environment variables are.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Overrides(pub(crate) Vec<(String, String)>);
+
+impl Source for Overrides {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        let origin = ORIGIN.to_string();
+        let mut root = Map::new();
+        for (key, value) in &self.0 {
+            let mut segments: Vec<_> = key.split('.').collect();
+            let Some(last) = segments.pop() else {
+                continue;
+            };
+            let mut table = &mut root;
+            for segment in segments {
+                let entry = table
+                    .entry(segment.to_string())
+                    .or_insert_with(|| Value::new(Some(&origin), Map::<String, Value>::new()));
+                if !matches!(entry.kind, ValueKind::Table(_)) {
+                    *entry = Value::new(Some(&origin), Map::<String, Value>::new());
+                }
+                let ValueKind::Table(nested) = &mut entry.kind else {
+                    unreachable!("Just made a table");
+                };
+                table = nested;
+            }
+            table.insert(last.to_string(), Value::new(Some(&origin), value.as_str()));
+        }
+        Ok(root)
+    }
+}
+
+/// Parse a `--set` argument.
+pub fn parse_override(argument: &str) -> Result<(String, String), String> {
+    match argument.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("`{}` is not of the form `key=value`", argument)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_override, Overrides};
+    use claims::{assert_err, assert_ok};
+    use config::Config;
+
+    #[test]
+    fn overrides_are_set_at_their_path() {
+        let config = Config::builder()
+            .add_source(Overrides(vec![
+                ("application.port".into(), "8080".into()),
+                ("application.host".into(), "127.0.0.1".into()),
+                ("redis_url".into(), "redis://redis:6379".into()),
+            ]))
+            .build()
+            .unwrap();
+
+        assert_eq!(assert_ok!(config.get::<u16>("application.port")), 8080);
+        assert_eq!(
+            assert_ok!(config.get::<String>("application.host")),
+            "127.0.0.1"
+        );
+        assert_eq!(
+            assert_ok!(config.get::<String>("redis_url")),
+            "redis://redis:6379"
+        );
+    }
+
+    #[test]
+    fn arguments_must_be_key_value_pairs() {
+        assert_eq!(
+            assert_ok!(parse_override(
+                "email_client.base_url=http://localhost:3000?a=b"
+            )),
+            (
+                "email_client.base_url".to_string(),
+                "http://localhost:3000?a=b".to_string()
+            )
+        );
+        for argument in ["application.port", "=8080"] {
+            assert_err!(parse_override(argument));
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use config::{Map, Value, ValueKind};
+use serde::de::DeserializeOwned;
+
+use super::{
+    ApplicationSettings, DatabaseSettings, EmailClientSettings, EmailTransportKind,
+    SchedulerSettings,
+};
+use crate::domain::SubscriberEmail;
+
+/// Smallest similarity for a known key to be suggested in place of an unknown one.
+const SUGGESTION_THRESHOLD: f64 = 0.8;
+
+/// A key of a configuration, `required` if it has no default.
+///
+/// Sections are keys too: a required key is only missing if its section is there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Key {
+    pub(crate) path: &'static str,
+    pub(crate) required: bool,
+}
+
+const fn required(path: &'static str) -> Key {
+    Key {
+        path,
+        required: true,
+    }
+}
+
+const fn optional(path: &'static str) -> Key {
+    Key {
+        path,
+        required: false,
+    }
+}
+
+/// Every key and section of [`Settings`](super::Settings), with `[]` standing for the elements of a
+/// list.
+///
+/// Used to report every missing key, and to suggest a key in place of an unknown one:
+/// serde tells which keys are unknown.
+pub(crate) const SETTINGS_KEYS: &[Key] = &[
+    required("application"),
+    required("application.base_url"),
+    required("application.hmac_secret"),
+    required("application.host"),
+    optional("application.log_filter"),
+    required("application.port"),
+    required("application.shutdown_timeout_milliseconds"),
+    required("application.templates_directory"),
+    optional("application.unsubscribe_link_ttl_days"),
+    required("database"),
+    required("database.database_name"),
+    required("database.host"),
+    required("database.migrate_on_startup"),
+    required("database.password"),
+    required("database.port"),
+    required("database.require_ssl"),
+    required("database.username"),
+    required("email_client"),
+    required("email_client.authorization_token"),
+    required("email_client.base_url"),
+    optional("email_client.circuit_breaker"),
+    required("email_client.circuit_breaker.cooldown_milliseconds"),
+    required("email_client.circuit_breaker.failure_threshold"),
+    optional("email_client.file"),
+    required("email_client.file.path"),
+    optional("email_client.kind"),
+    optional("email_client.postmark_webhook"),
+    optional("email_client.postmark_webhook.password"),
+    optional("email_client.postmark_webhook.secret"),
+    optional("email_client.postmark_webhook.username"),
+    optional("email_client.rate_limit"),
+    required("email_client.rate_limit.burst"),
+    required("email_client.rate_limit.requests_per_second"),
+    required("email_client.sender_email"),
+    optional("email_client.smtp"),
+    required("email_client.smtp.host"),
+    optional("email_client.smtp.password"),
+    required("email_client.smtp.port"),
+    optional("email_client.smtp.tls"),
+    optional("email_client.smtp.username"),
+    required("email_client.timeout_milliseconds"),
+    required("redis_url"),
+    required("scheduler"),
+    required("scheduler.jobs"),
+    required("scheduler.jobs[].catch_up"),
+    required("scheduler.jobs[].job_type"),
+    required("scheduler.jobs[].name"),
+    optional("scheduler.jobs[].payload"),
+    required("scheduler.jobs[].schedule"),
+];
+
+/// Deserialize `value`, reporting every unknown key and every missing key along with
+/// the error that stopped the deserialization, if any.
+///
+/// `known_keys` tell which keys are required, and are suggested in place of unknown
+/// keys that look like them.
+pub(crate) fn deserialize<T: DeserializeOwned>(
+    value: Value,
+    known_keys: &[Key],
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    let missing_keys = missing_keys(&value, known_keys);
+    let mut unknown_keys = Vec::new();
+    let mut record_unknown_key = |path: serde_ignored::Path| {
+        unknown_keys.push(path_to_string(&path));
+    };
+    let deserializer = serde_ignored::Deserializer::new(value, &mut record_unknown_key);
+    let outcome = serde_path_to_error::deserialize::<_, T>(deserializer);
+    unknown_keys.sort();
+    problems.extend(unknown_keys.iter().map(|key| unknown_key(key, known_keys)));
+    problems.extend(
+        missing_keys
+            .iter()
+            .map(|key| format!("missing key `{}`", key)),
+    );
+    match outcome {
+        Ok(value) => Some(value),
+        Err(e) => {
+            let path = match e.path().iter().next() {
+                Some(_) => e.path().to_string(),
+                None => String::new(),
+            };
+            let problem = match e.inner() {
+                // `config` names the field with a path of its own, e.g. `jobs[1]name`.
+                config::ConfigError::NotFound(key) => {
+                    let field = key.rsplit(['.', ']']).next().unwrap_or(key);
+                    format!("missing key `{}`", join(&path, field))
+                }
+                // Names the key already.
+                config::ConfigError::Type { key: Some(_), .. } => e.inner().to_string(),
+                inner if path.is_empty() => inner.to_string(),
+                inner => format!("`{}`: {}", path, inner),
+            };
+            // A missing key has been reported already.
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+            None
+        }
+    }
+}
+
+/// The required keys absent from `value`, e.g. `jobs[1].name`, in the order of
+/// `known_keys`.
+///
+/// Serde stops at the first one, hence the walk.
+fn missing_keys(value: &Value, known_keys: &[Key]) -> Vec<String> {
+    let mut missing = Vec::new();
+    for key in known_keys.iter().filter(|key| key.required) {
+        let (section, field) = key.path.rsplit_once('.').unwrap_or(("", key.path));
+        for (path, table) in tables_at(value, String::new(), section) {
+            if !table.contains_key(field) {
+                missing.push(join(&path, field));
+            }
+        }
+    }
+    missing
+}
+
+/// The tables at the generic `section`, e.g. one per job for `jobs[]`, along with
+/// their actual paths. Sections that are absent or not tables are left out.
+fn tables_at<'a>(
+    value: &'a Value,
+    path: String,
+    section: &str,
+) -> Vec<(String, &'a Map<String, Value>)> {
+    let ValueKind::Table(table) = &value.kind else {
+        return vec![];
+    };
+    if section.is_empty() {
+        return vec![(path, table)];
+    }
+    let (head, rest) = section.split_once('.').unwrap_or((section, ""));
+    match head.strip_suffix("[]") {
+        Some(list) => match table.get(list).map(|value| &value.kind) {
+            Some(ValueKind::Array(elements)) => elements
+                .iter()
+                .enumerate()
+                .flat_map(|(i, element)| {
+                    tables_at(element, format!("{}[{}]", join(&path, list), i), rest)
+                })
+                .collect(),
+            _ => vec![],
+        },
+        None => match table.get(head) {
+            Some(nested) => tables_at(nested, join(&path, head), rest),
+            None => vec![],
+        },
+    }
+}
+
+/// `path` as `jobs[0].name`.
+fn path_to_string(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => {
+            format!("{}[{}]", path_to_string(parent), index)
+        }
+        serde_ignored::Path::Map { parent, key } => join(&path_to_string(parent), key),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => path_to_string(parent),
+    }
+}
+
+/// Check the values that deserialized fine but make no sense.
+///
+/// Each section is checked on its own, so that a section that cannot be deserialized
+/// does not hide the problems of the others.
+pub(crate) fn check_values(merged: &Value, problems: &mut Vec<String>) {
+    if let Some(application) = section(merged, "application") {
+        check_application(&application, problems);
+    }
+    if let Some(database) = section(merged, "database") {
+        check_database(&database, problems);
+    }
+    if let Some(email_client) = section(merged, "email_client") {
+        check_email_client(&email_client, problems);
+    }
+    if let Some(scheduler) = section(merged, "scheduler") {
+        check_scheduler(&scheduler, problems);
+    }
+}
+
+/// The top-level section `key` of `merged`, if it deserializes.
+fn section<T: DeserializeOwned>(merged: &Value, key: &str) -> Option<T> {
+    let ValueKind::Table(table) = &merged.kind else {
+        return None;
+    };
+    table.get(key)?.clone().try_deserialize().ok()
+}
+
+fn check_application(application: &ApplicationSettings, problems: &mut Vec<String>) {
+    check(
+        problems,
+        application.port != 0,
+        "`application.port` must not be 0",
+    );
+    check(
+        problems,
+        !application.host.is_empty(),
+        "`application.host` must not be empty",
+    );
+    check(
+        problems,
+        !application.base_url.is_empty(),
+        "`application.base_url` must not be empty",
+    );
+    check(
+        problems,
+        application.shutdown_timeout_milliseconds > 0,
+        "`application.shutdown_timeout_milliseconds` must be greater than 0",
+    );
+    check(
+        problems,
+        application.unsubscribe_link_ttl_days != Some(0),
+        "`application.unsubscribe_link_ttl_days` must be greater than 0, or unset",
+    );
+
+    if let Err(e) = application.log_filter() {
+        problems.push(format!("`application.log_filter`: {}", e));
+    }
+}
+
+fn check_database(database: &DatabaseSettings, problems: &mut Vec<String>) {
+    check(
+        problems,
+        database.port != 0,
+        "`database.port` must not be 0",
+    );
+    check(
+        problems,
+        !database.host.is_empty(),
+        "`database.host` must not be empty",
+    );
+}
+
+fn check_email_client(email_client: &EmailClientSettings, problems: &mut Vec<String>) {
+    if let Err(e) = SubscriberEmail::parse(email_client.sender_email.clone()) {
+        problems.push(format!("`email_client.sender_email`: {}", e));
+    }
+    check(
+        problems,
+        email_client.timeout_milliseconds > 0,
+        "`email_client.timeout_milliseconds` must be greater than 0",
+    );
+    check(
+        problems,
+        email_client.kind != EmailTransportKind::Smtp || email_client.smtp.is_some(),
+        "`email_client.smtp` is required when `email_client.kind` is `smtp`",
+    );
+    check(
+        problems,
+        email_client.kind != EmailTransportKind::File || email_client.file.is_some(),
+        "`email_client.file` is required when `email_client.kind` is `file`",
+    );
+    if let Some(smtp) = &email_client.smtp {
+        check(
+            problems,
+            !smtp.host.is_empty(),
+            "`email_client.smtp.host` must not be empty",
+        );
+        check(
+            problems,
+            smtp.port != 0,
+            "`email_client.smtp.port` must not be 0",
+        );
+    }
+    if let Some(rate_limit) = &email_client.rate_limit {
+        check(
+            problems,
+            rate_limit.requests_per_second > 0.0,
+            "`email_client.rate_limit.requests_per_second` must be greater than 0",
+        );
+        check(
+            problems,
+            rate_limit.burst > 0,
+            "`email_client.rate_limit.burst` must be greater than 0",
+        );
+    }
+    if let Some(circuit_breaker) = &email_client.circuit_breaker {
+        check(
+            problems,
+            circuit_breaker.failure_threshold > 0,
+            "`email_client.circuit_breaker.failure_threshold` must be greater than 0",
+        );
+    }
+    if let Some(webhook) = &email_client.postmark_webhook {
+        check(
+            problems,
+            webhook.username.is_some() == webhook.password.is_some(),
+            "`email_client.postmark_webhook` needs both a `username` and a `password`",
+        );
+    }
+}
+
+fn check_scheduler(scheduler: &SchedulerSettings, problems: &mut Vec<String>) {
+    for (i, job) in scheduler.jobs.iter().enumerate() {
+        if let Err(e) = job.schedule() {
+            problems.push(format!("`scheduler.jobs[{}].schedule`: {}", i, e));
+        }
+    }
+}
+
+fn check(problems: &mut Vec<String>, ok: bool, problem: &str) {
+    if !ok {
+        problems.push(problem.to_string());
+    }
+}
+
+/// Report the unknown key at `path`, suggesting the known key it looks the most like.
+fn unknown_key(path: &str, known_keys: &[Key]) -> String {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (parent, key),
+        None => ("", path),
+    };
+    let generic_parent = without_indices(parent);
+    let suggestion = known_keys
+        .iter()
+        .filter_map(|known| {
+            let (known_parent, known_key) = known.path.rsplit_once('.').unwrap_or(("", known.path));
+            (known_parent == generic_parent).then_some(known_key)
+        })
+        .map(|candidate| (strsim::jaro_winkler(key, candidate), candidate))
+        .filter(|(similarity, _)| *similarity >= SUGGESTION_THRESHOLD)
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+    match suggestion {
+        Some((_, candidate)) => format!(
+            "unknown key `{}`, did you mean `{}`?",
+            path,
+            join(parent, candidate)
+        ),
+        None => format!("unknown key `{}`", path),
+    }
+}
+
+/// `path` with list indices replaced by `[]`, e.g. `jobs[]` for `jobs[0]`.
+fn without_indices(path: &str) -> String {
+    let mut generic = String::with_capacity(path.len());
+    let mut in_index = false;
+    for c in path.chars() {
+        match c {
+            '[' => {
+                in_index = true;
+                generic.push_str("[]");
+            }
+            ']' => in_index = false,
+            c if !in_index => generic.push(c),
+            _ => {}
+        }
+    }
+    generic
+}
+
+pub(crate) fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize, optional, required, Key};
+    use config::{Config, FileFormat};
+
+    const KNOWN_KEYS: &[Key] = &[
+        required("database"),
+        optional("database.pool_size"),
+        required("database.require_ssl"),
+        required("database.username"),
+        optional("jobs"),
+        required("jobs[].name"),
+        optional("jobs[].payload"),
+        required("redis_url"),
+    ];
+
+    #[derive(serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Example {
+        database: Database,
+        #[serde(default)]
+        jobs: Vec<Job>,
+        redis_url: String,
+    }
+
+    #[derive(serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Database {
+        username: String,
+        require_ssl: bool,
+        #[serde(default)]
+        pool_size: Option<u32>,
+    }
+
+    #[derive(serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Job {
+        name: String,
+        #[serde(default)]
+        payload: serde_json::Value,
+    }
+
+    fn problems(yaml: &str) -> Vec<String> {
+        let config = Config::builder()
+            .add_source(config::File::from_str(yaml, FileFormat::Yaml))
+            .build()
+            .unwrap();
+        let value = config::Value::new(None, config::Source::collect(&config).unwrap());
+        let mut problems = Vec::new();
+        deserialize::<Example>(value, KNOWN_KEYS, &mut problems);
+        problems
+    }
+
+    #[test]
+    fn a_valid_configuration_has_no_problems() {
+        let problems = problems(
+            r#"
+database:
+    username: "postgres"
+    require_ssl: false
+jobs:
+    - name: "cleanup"
+      payload:
+          anything: 1
+redis_url: "redis://127.0.0.1:6379"
+"#,
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn misspelled_keys_are_reported_with_a_suggestion() {
+        let problems = problems(
+            r#"
+database:
+    username: "postgres"
+    require_ssl: false
+    pool_sise: 10
+redis_uri: "redis://127.0.0.1:6379"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "unknown key `database.pool_sise`, did you mean `database.pool_size`?",
+                "unknown key `redis_uri`, did you mean `redis_url`?",
+                "missing key `redis_url`",
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_of_list_elements_are_checked() {
+        let problems = problems(
+            r#"
+database:
+    username: "postgres"
+    require_ssl: false
+jobs:
+    - name: "cleanup"
+    - nmae: "cleanup"
+redis_url: "redis://127.0.0.1:6379"
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "unknown key `jobs[1].nmae`, did you mean `jobs[1].name`?",
+                "missing key `jobs[1].name`",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_missing_key_is_reported() {
+        let problems = problems(
+            r#"
+database:
+    require_ssl: false
+jobs:
+    - payload: {}
+    - name: "cleanup"
+    - payload: {}
+"#,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "missing key `database.username`",
+                "missing key `jobs[0].name`",
+                "missing key `jobs[2].name`",
+                "missing key `redis_url`",
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_of_an_absent_section_are_not_reported() {
+        let problems = problems(r#"redis_url: "redis://127.0.0.1:6379""#);
+        assert_eq!(problems, vec!["missing key `database`"]);
+    }
+
+    #[test]
+    fn unrelated_unknown_keys_get_no_suggestion() {
+        let problems = problems(
+            r#"
+database:
+    username: "postgres"
+    require_ssl: false
+redis_url: "redis://127.0.0.1:6379"
+telemetry: true
+"#,
+        );
+        assert_eq!(problems, vec!["unknown key `telemetry`"]);
+    }
+}
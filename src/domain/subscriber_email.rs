@@ -4,13 +4,41 @@ use validator::ValidateEmail;
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
+    /// Non-ASCII characters are allowed in the local part, as RFC 6531 does; the
+    /// rest of the address is checked against the HTML5 rules.
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if s.validate_email() {
+        if with_ascii_local_part(&s).validate_email() {
             Ok(Self(s))
         } else {
             Err(format!("{} is not a valid subscriber email", s))
         }
     }
+
+    /// Lowercased, so that differently-cased spellings of an address compare equal.
+    pub fn normalized(&self) -> String {
+        self.0.to_lowercase()
+    }
+
+    /// The part after the `@`, lowercased.
+    pub fn domain(&self) -> String {
+        let (_, domain) = self.0.rsplit_once('@').unwrap_or_default();
+        domain.to_lowercase()
+    }
+}
+
+/// Replaces every non-ASCII character before the last `@` with an ASCII letter,
+/// so that the validator's ASCII-only local part rule admits UTF-8 local parts.
+fn with_ascii_local_part(s: &str) -> String {
+    match s.rsplit_once('@') {
+        Some((local, domain)) => {
+            let local: String = local
+                .chars()
+                .map(|c| if c.is_ascii() { c } else { 'a' })
+                .collect();
+            format!("{}@{}", local, domain)
+        }
+        None => s.to_string(),
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -54,6 +82,25 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn non_ascii_local_parts_are_accepted() {
+        let email = "宜芳@example.net".to_string();
+        assert!(SubscriberEmail::parse(email).is_ok());
+    }
+
+    #[test]
+    fn non_ascii_local_parts_are_still_checked_for_length() {
+        let email = format!("{}@example.net", "宜".repeat(65));
+        assert_err!(SubscriberEmail::parse(email));
+    }
+
+    #[test]
+    fn normalized_emails_and_domains_are_lowercased() {
+        let email = SubscriberEmail::parse("Ursula@Example.COM".to_string()).unwrap();
+        assert_eq!(email.normalized(), "ursula@example.com");
+        assert_eq!(email.domain(), "example.com");
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -0,0 +1,73 @@
+use rand::distr::Alphanumeric;
+use rand::Rng;
+
+const TOKEN_LENGTH: usize = 25;
+
+#[derive(Debug, Clone)]
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    /// Generate a random 25-characters-long case-sensitive subscription token.
+    pub fn generate() -> SubscriptionToken {
+        let mut rng = rand::rng();
+        let token = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(TOKEN_LENGTH)
+            .collect();
+        Self(token)
+    }
+
+    pub fn parse(s: String) -> Result<SubscriptionToken, String> {
+        let has_expected_length = s.chars().count() == TOKEN_LENGTH;
+        let is_alphanumeric = s.chars().all(|c| c.is_ascii_alphanumeric());
+
+        if has_expected_length && is_alphanumeric {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not a valid subscription token.", s))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriptionToken;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn generated_tokens_are_valid() {
+        let token = SubscriptionToken::generate();
+        assert_ok!(SubscriptionToken::parse(token.as_ref().to_string()));
+    }
+
+    #[test]
+    fn generated_tokens_are_unique() {
+        let first = SubscriptionToken::generate();
+        let second = SubscriptionToken::generate();
+        assert_ne!(first.as_ref(), second.as_ref());
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        let token = "".to_string();
+        assert_err!(SubscriptionToken::parse(token));
+    }
+
+    #[test]
+    fn a_token_with_the_wrong_length_is_rejected() {
+        let token = "a".repeat(26);
+        assert_err!(SubscriptionToken::parse(token));
+    }
+
+    #[test]
+    fn a_token_with_non_alphanumeric_characters_is_rejected() {
+        let token = format!("{}-", "a".repeat(24));
+        assert_err!(SubscriptionToken::parse(token));
+    }
+}
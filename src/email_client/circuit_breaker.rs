@@ -0,0 +1,255 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stops `EmailClient` from calling a provider that keeps failing.
+///
+/// After `failure_threshold` consecutive failures the breaker opens and requests fail
+/// straight away. Once `cooldown` has elapsed a single probe request is let through:
+/// the breaker closes if it succeeds and opens again if it fails.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<Generation>,
+}
+
+/// The breaker state, numbered so that the outcome of a request admitted under an
+/// earlier state can be told apart: a request let through while closed must not
+/// close a breaker that has opened since.
+#[derive(Debug, Clone, Copy)]
+struct Generation {
+    number: u64,
+    state: BreakerState,
+}
+
+/// Handed out by [`CircuitBreaker::allow_request`], and given back with the outcome
+/// of the request it let through.
+#[derive(Debug, Clone, Copy)]
+pub struct Permit {
+    generation: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed {
+        consecutive_failures: u32,
+    },
+    Open {
+        since: Instant,
+    },
+    /// A probe is in flight. Another one is let through if it has not reported back
+    /// within `cooldown`, e.g. because its request was cancelled.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+impl BreakerState {
+    fn name(&self) -> &'static str {
+        match self {
+            BreakerState::Closed { .. } => "closed",
+            BreakerState::Open { .. } => "open",
+            BreakerState::HalfOpen { .. } => "half_open",
+        }
+    }
+
+    /// Value of the `email_client_circuit_breaker_state` gauge.
+    fn gauge_value(&self) -> f64 {
+        match self {
+            BreakerState::Closed { .. } => 0.0,
+            BreakerState::HalfOpen { .. } => 1.0,
+            BreakerState::Open { .. } => 2.0,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        let state = BreakerState::Closed {
+            consecutive_failures: 0,
+        };
+        metrics::gauge!("email_client_circuit_breaker_state").set(state.gauge_value());
+        Self {
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(Generation { number: 0, state }),
+        }
+    }
+
+    /// A permit if a request may be sent now.
+    pub fn allow_request(&self) -> Option<Permit> {
+        self.allow_request_at(Instant::now())
+    }
+
+    pub fn record_success(&self, permit: Permit) {
+        self.transition(Some(permit), |_| BreakerState::Closed {
+            consecutive_failures: 0,
+        });
+    }
+
+    pub fn record_failure(&self, permit: Permit) {
+        self.record_failure_at(permit, Instant::now())
+    }
+
+    fn allow_request_at(&self, now: Instant) -> Option<Permit> {
+        let mut allowed = true;
+        let generation = self.transition(None, |state| match state {
+            BreakerState::Closed { .. } => state,
+            BreakerState::Open { since } | BreakerState::HalfOpen { since }
+                if now.saturating_duration_since(since) >= self.cooldown =>
+            {
+                BreakerState::HalfOpen { since: now }
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                allowed = false;
+                state
+            }
+        });
+        if !allowed {
+            metrics::counter!("email_client_circuit_breaker_rejections_total").increment(1);
+            return None;
+        }
+        Some(Permit { generation })
+    }
+
+    fn record_failure_at(&self, permit: Permit, now: Instant) {
+        self.transition(Some(permit), |state| match state {
+            BreakerState::Closed {
+                consecutive_failures,
+            } if consecutive_failures + 1 < self.failure_threshold => BreakerState::Closed {
+                consecutive_failures: consecutive_failures + 1,
+            },
+            _ => BreakerState::Open { since: now },
+        });
+    }
+
+    /// Move to the state `next` returns and return the generation the breaker is in
+    /// afterwards. Nothing changes if `permit` was handed out under an earlier state.
+    fn transition(
+        &self,
+        permit: Option<Permit>,
+        next: impl FnOnce(BreakerState) -> BreakerState,
+    ) -> u64 {
+        let mut generation = self.state.lock().unwrap();
+        if permit.is_some_and(|permit| permit.generation != generation.number) {
+            return generation.number;
+        }
+        let previous = generation.state;
+        let state = next(previous);
+        generation.state = state;
+        // Only a failure count changing while closed keeps earlier requests current.
+        let still_closed = matches!(
+            (previous, state),
+            (BreakerState::Closed { .. }, BreakerState::Closed { .. })
+        );
+        if previous != state && !still_closed {
+            generation.number += 1;
+        }
+        if previous.name() != state.name() {
+            tracing::warn!(
+                from = previous.name(),
+                to = state.name(),
+                "Email circuit breaker changed state"
+            );
+            metrics::gauge!("email_client_circuit_breaker_state").set(state.gauge_value());
+        }
+        generation.number
+    }
+
+    #[cfg(test)]
+    fn state(&self) -> &'static str {
+        self.state.lock().unwrap().state.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CircuitBreaker;
+    use std::time::{Duration, Instant};
+
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    fn open(breaker: &CircuitBreaker, now: Instant) {
+        let permit = breaker.allow_request_at(now).unwrap();
+        breaker.record_failure_at(permit, now);
+    }
+
+    #[test]
+    fn the_breaker_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            let permit = breaker.allow_request_at(now).unwrap();
+            breaker.record_failure_at(permit, now);
+        }
+        assert_eq!(breaker.state(), "closed");
+
+        let permit = breaker.allow_request_at(now).unwrap();
+        breaker.record_failure_at(permit, now);
+        assert_eq!(breaker.state(), "open");
+        assert!(breaker.allow_request_at(now).is_none());
+    }
+
+    #[test]
+    fn a_success_resets_the_failure_count() {
+        let breaker = CircuitBreaker::new(2, COOLDOWN);
+        let now = Instant::now();
+
+        let permit = breaker.allow_request_at(now).unwrap();
+        breaker.record_failure_at(permit, now);
+        breaker.record_success(breaker.allow_request_at(now).unwrap());
+        let permit = breaker.allow_request_at(now).unwrap();
+        breaker.record_failure_at(permit, now);
+
+        assert_eq!(breaker.state(), "closed");
+    }
+
+    #[test]
+    fn a_single_probe_is_let_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        let opened_at = Instant::now();
+        open(&breaker, opened_at);
+
+        let after_cooldown = opened_at + COOLDOWN;
+        assert!(breaker.allow_request_at(after_cooldown).is_some());
+        assert_eq!(breaker.state(), "half_open");
+        assert!(breaker.allow_request_at(after_cooldown).is_none());
+    }
+
+    #[test]
+    fn the_probe_outcome_closes_or_reopens_the_breaker() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        let opened_at = Instant::now();
+        open(&breaker, opened_at);
+        let after_cooldown = opened_at + COOLDOWN;
+
+        let probe = breaker.allow_request_at(after_cooldown).unwrap();
+        breaker.record_failure_at(probe, after_cooldown);
+        assert_eq!(breaker.state(), "open");
+        assert!(breaker.allow_request_at(after_cooldown).is_none());
+
+        let probe = breaker.allow_request_at(after_cooldown + COOLDOWN).unwrap();
+        breaker.record_success(probe);
+        assert_eq!(breaker.state(), "closed");
+    }
+
+    #[test]
+    fn requests_let_through_before_the_breaker_opened_do_not_close_it() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        let now = Instant::now();
+        let slow = breaker.allow_request_at(now).unwrap();
+        open(&breaker, now);
+
+        breaker.record_success(slow);
+        assert_eq!(breaker.state(), "open");
+
+        // Nor does a probe that was given up on once another one went out.
+        let after_cooldown = now + COOLDOWN;
+        let stale_probe = breaker.allow_request_at(after_cooldown).unwrap();
+        let probe = breaker.allow_request_at(after_cooldown + COOLDOWN).unwrap();
+        breaker.record_success(stale_probe);
+        assert_eq!(breaker.state(), "half_open");
+        breaker.record_success(probe);
+        assert_eq!(breaker.state(), "closed");
+    }
+}
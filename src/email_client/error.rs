@@ -0,0 +1,75 @@
+use reqwest::StatusCode;
+
+use crate::routes::error_chain_fmt;
+
+/// Postmark's `ErrorCode` while the service is down for maintenance.
+const POSTMARK_MAINTENANCE: u32 = 100;
+
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    /// The provider answered, and refused the email.
+    #[error("The email provider rejected the email ({status}): {message}")]
+    Rejected {
+        status: StatusCode,
+        /// Postmark's `ErrorCode`, if the response body carried one.
+        error_code: Option<u32>,
+        message: String,
+    },
+    /// No answer from the provider: timeout, connection refused, ...
+    #[error("Failed to reach the email provider")]
+    Request(#[source] reqwest::Error),
+    /// Too many recent failures: the provider is not being contacted for a while.
+    #[error("The email provider is unavailable, the circuit breaker is open")]
+    CircuitOpen,
+    #[error("Failed to deliver the email over SMTP")]
+    Smtp(#[source] lettre::transport::smtp::Error),
+    #[error("Failed to write the email to disk")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl EmailError {
+    /// Whether sending the same email again later could succeed.
+    ///
+    /// Timeouts, 5xx and rate limiting are transient; an inactive recipient or a bad
+    /// server token will fail the same way every time.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            EmailError::Rejected {
+                status, error_code, ..
+            } => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *error_code == Some(POSTMARK_MAINTENANCE)
+            }
+            EmailError::Request(e) => !e.is_builder(),
+            EmailError::CircuitOpen => true,
+            EmailError::Smtp(e) => !e.is_permanent() && !e.is_client(),
+            EmailError::Io(_) => true,
+            // Malformed emails and responses we could not make sense of.
+            EmailError::UnexpectedError(_) => false,
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            EmailError::Rejected { status, .. } => Some(*status),
+            EmailError::Request(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    pub fn error_code(&self) -> Option<u32> {
+        match self {
+            EmailError::Rejected { error_code, .. } => *error_code,
+            _ => None,
+        }
+    }
+}
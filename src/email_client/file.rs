@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::email_client::{mime_message, Email, EmailError, EmailTransport, SentEmail};
+
+/// Appends emails to a local mbox file instead of sending them.
+///
+/// Meant for development and CI: any mail client (or `grep`) can read the result.
+pub struct FileTransport {
+    path: PathBuf,
+    /// Keeps concurrent deliveries from interleaving their writes.
+    lock: Mutex<()>,
+}
+
+impl FileTransport {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<SentEmail, EmailError> {
+        let message = mime_message(email)?.formatted();
+        let entry = mbox_entry(email.from.as_ref(), &String::from_utf8_lossy(&message));
+
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(entry.as_bytes()).await?;
+        file.flush().await?;
+        Ok(SentEmail::default())
+    }
+}
+
+/// Wrap a formatted message in an mboxrd entry: a `From ` separator line, the message
+/// with `From ` lines quoted, and a trailing blank line.
+fn mbox_entry(sender: &str, message: &str) -> String {
+    let mut entry = format!(
+        "From {} {}\n",
+        sender,
+        chrono::Utc::now().format("%a %b %e %H:%M:%S %Y")
+    );
+    for line in message.lines() {
+        if line.trim_start_matches('>').starts_with("From ") {
+            entry.push('>');
+        }
+        entry.push_str(line);
+        entry.push('\n');
+    }
+    entry.push('\n');
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mbox_entry, FileTransport};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailClient;
+
+    #[test]
+    fn mbox_entry_quotes_from_lines_in_the_body() {
+        let entry = mbox_entry(
+            "a@example.com",
+            "Subject: Hi\r\n\r\nFrom here on\r\n>From there\r\n",
+        );
+
+        let lines: Vec<_> = entry.lines().collect();
+        assert!(lines[0].starts_with("From a@example.com "));
+        assert_eq!(lines[3], ">From here on");
+        assert_eq!(lines[4], ">>From there");
+        assert!(entry.ends_with("\n\n"));
+    }
+
+    #[tokio::test]
+    async fn emails_are_appended_to_the_mbox_file() {
+        let path = std::env::temp_dir().join(format!("{}.mbox", uuid::Uuid::new_v4()));
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            FileTransport::new(path.clone()),
+        );
+        let recipient = SubscriberEmail::parse("reader@example.com".into()).unwrap();
+
+        for subject in ["First", "Second"] {
+            email_client
+                .send_email(&recipient, subject, "<p>Hi!</p>", "Hi!")
+                .await
+                .unwrap();
+        }
+
+        let mbox = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let separators = mbox
+            .lines()
+            .filter(|line| line.starts_with("From newsletter@example.com "))
+            .count();
+        assert_eq!(separators, 2);
+        assert!(mbox.contains("Subject: First"));
+        assert!(mbox.contains("Subject: Second"));
+    }
+}
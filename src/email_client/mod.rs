@@ -0,0 +1,396 @@
+mod circuit_breaker;
+mod error;
+mod file;
+mod postmark;
+mod rate_limit;
+mod smtp;
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use lettre::message::{
+    header::{HeaderName, HeaderValue},
+    Mailbox, MultiPart,
+};
+
+use crate::domain::SubscriberEmail;
+
+pub use circuit_breaker::{CircuitBreaker, Permit};
+pub use error::EmailError;
+pub use file::FileTransport;
+pub use postmark::PostmarkTransport;
+pub use rate_limit::RateLimiter;
+pub use smtp::{SmtpTls, SmtpTransport};
+
+/// Something that can deliver an [`Email`]: an HTTP API, an SMTP relay, a file on disk...
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync + 'static {
+    async fn send(&self, email: &Email<'_>) -> Result<SentEmail, EmailError>;
+
+    /// Deliver several emails, reporting the outcome for each recipient.
+    ///
+    /// Sends them one by one unless the transport has a cheaper way to do it.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> BatchOutcome {
+        let mut outcome = BatchOutcome::default();
+        for email in emails {
+            match self.send(email).await {
+                Ok(_) => outcome.succeeded.push(email.to.clone()),
+                Err(e) => outcome.failed.push(FailedDelivery {
+                    recipient: email.to.clone(),
+                    error: Arc::new(e),
+                }),
+            }
+        }
+        outcome
+    }
+}
+
+/// A single outgoing email, as handed over to an [`EmailTransport`].
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub headers: &'a [EmailHeader],
+}
+
+/// An email to send as part of a batch, see [`EmailClient::send_batch`].
+#[derive(Debug, Clone)]
+pub struct OutgoingEmail {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub headers: Vec<EmailHeader>,
+}
+
+/// Which recipients of a batch got their email and which did not.
+#[derive(Debug, Default)]
+pub struct BatchOutcome {
+    pub succeeded: Vec<SubscriberEmail>,
+    pub failed: Vec<FailedDelivery>,
+}
+
+#[derive(Debug)]
+pub struct FailedDelivery {
+    pub recipient: SubscriberEmail,
+    /// Shared between the recipients of a batch that failed as a whole.
+    pub error: Arc<EmailError>,
+}
+
+/// What the transport tells us about an email it accepted.
+#[derive(Debug, Default)]
+pub struct SentEmail {
+    /// Postmark's `MessageID`, to correlate with later bounce or delivery events.
+    pub message_id: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Clone)]
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Arc<dyn EmailTransport>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailTransport) -> Self {
+        Self {
+            sender,
+            transport: Arc::new(transport),
+            rate_limiter: None,
+            circuit_breaker: None,
+        }
+    }
+
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(Arc::new(rate_limiter));
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(Arc::new(circuit_breaker));
+        self
+    }
+
+    /// Share the circuit breaker of `previous`, so that a client replacing it does not
+    /// forget about a provider that is down.
+    pub fn with_circuit_breaker_of(mut self, previous: &EmailClient) -> Self {
+        self.circuit_breaker = previous.circuit_breaker.clone();
+        self
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<SentEmail, EmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Like [`EmailClient::send_email`], with extra MIME headers such as `List-Unsubscribe`.
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, EmailError> {
+        let email = Email {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+        };
+        let permit = self.before_request(1).await?;
+        let outcome = self.transport.send(&email).await;
+        self.after_request(permit, outcome.as_ref().err());
+        outcome
+    }
+
+    /// Send every email in `emails`, in as few round trips as the transport allows.
+    ///
+    /// Failures are reported per recipient rather than as an error, so that callers
+    /// can retry only the emails that did not go through. The rate limiter counts
+    /// every email, while the circuit breaker counts the batch as a single request.
+    pub async fn send_batch(&self, emails: &[OutgoingEmail]) -> BatchOutcome {
+        let emails: Vec<_> = emails
+            .iter()
+            .map(|email| Email {
+                from: &self.sender,
+                to: &email.recipient,
+                subject: &email.subject,
+                html_body: &email.html_content,
+                text_body: &email.text_content,
+                headers: &email.headers,
+            })
+            .collect();
+        let permit = match self.before_request(emails.len()).await {
+            Ok(permit) => permit,
+            Err(e) => {
+                let error = Arc::new(e);
+                return BatchOutcome {
+                    succeeded: vec![],
+                    failed: emails
+                        .iter()
+                        .map(|email| FailedDelivery {
+                            recipient: email.to.clone(),
+                            error: error.clone(),
+                        })
+                        .collect(),
+                };
+            }
+        };
+        let outcome = self.transport.send_batch(&emails).await;
+        // The provider is considered down only if nothing went through.
+        let failure = if outcome.succeeded.is_empty() {
+            outcome.failed.first().map(|failure| &*failure.error)
+        } else {
+            None
+        };
+        self.after_request(permit, failure);
+        outcome
+    }
+
+    /// Fail fast if the circuit breaker is open, then wait until the rate limiter lets
+    /// `n_emails` emails through.
+    async fn before_request(&self, n_emails: usize) -> Result<Option<Permit>, EmailError> {
+        let permit = match &self.circuit_breaker {
+            Some(circuit_breaker) => match circuit_breaker.allow_request() {
+                Some(permit) => Some(permit),
+                None => return Err(EmailError::CircuitOpen),
+            },
+            None => None,
+        };
+        if let Some(rate_limiter) = &self.rate_limiter {
+            for _ in 0..n_emails {
+                rate_limiter.acquire().await;
+            }
+        }
+        Ok(permit)
+    }
+
+    /// Report the outcome of a request to the circuit breaker.
+    ///
+    /// Only failures worth retrying count: a permanent error means the provider is up
+    /// and answering.
+    fn after_request(&self, permit: Option<Permit>, error: Option<&EmailError>) {
+        let (Some(circuit_breaker), Some(permit)) = (&self.circuit_breaker, permit) else {
+            return;
+        };
+        match error {
+            Some(e) if e.is_retryable() => circuit_breaker.record_failure(permit),
+            _ => circuit_breaker.record_success(permit),
+        }
+    }
+}
+
+/// The [`EmailClient`] in use, replaced when the configuration is reloaded.
+#[derive(Clone)]
+pub struct SharedEmailClient(Arc<ArcSwap<EmailClient>>);
+
+impl SharedEmailClient {
+    pub fn new(client: EmailClient) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(client)))
+    }
+
+    /// The current client. Emails in flight keep the client they started with.
+    pub fn load(&self) -> Arc<EmailClient> {
+        self.0.load_full()
+    }
+
+    pub fn store(&self, client: EmailClient) {
+        self.0.store(Arc::new(client));
+    }
+}
+
+/// Render `email` as a multipart/alternative MIME message, for the transports that
+/// speak raw RFC 5322 rather than an HTTP API.
+fn mime_message(email: &Email<'_>) -> Result<lettre::Message, anyhow::Error> {
+    let from: Mailbox = email.from.as_ref().parse()?;
+    let to: Mailbox = email.to.as_ref().parse()?;
+    let mut builder = lettre::Message::builder()
+        .from(from)
+        .to(to)
+        .subject(email.subject);
+    for header in email.headers {
+        let name = HeaderName::new_from_ascii(header.name.clone())?;
+        builder = builder.raw_header(HeaderValue::new(name, header.value.clone()));
+    }
+    let message = builder.multipart(MultiPart::alternative_plain_html(
+        email.text_body.to_owned(),
+        email.html_body.to_owned(),
+    ))?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        mime_message, CircuitBreaker, Email, EmailClient, EmailError, EmailHeader, OutgoingEmail,
+        RateLimiter,
+    };
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::PostmarkTransport;
+    use claims::assert_err;
+    use std::time::{Duration, Instant};
+    use wiremock::matchers::any;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn mime_message_carries_the_extra_headers() {
+        let from = SubscriberEmail::parse("newsletter@example.com".into()).unwrap();
+        let to = SubscriberEmail::parse("reader@example.com".into()).unwrap();
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        }];
+        let email = Email {
+            from: &from,
+            to: &to,
+            subject: "Issue #1",
+            html_body: "<p>Hi!</p>",
+            text_body: "Hi!",
+            headers: &headers,
+        };
+
+        let formatted = String::from_utf8(mime_message(&email).unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("To: reader@example.com"));
+        assert!(formatted.contains("Subject: Issue #1"));
+        assert!(formatted.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(formatted.contains("multipart/alternative"));
+    }
+
+    #[tokio::test]
+    async fn the_provider_is_not_called_while_the_circuit_breaker_is_open() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let sender = SubscriberEmail::parse("newsletter@example.com".into()).unwrap();
+        let recipient = SubscriberEmail::parse("reader@example.com".into()).unwrap();
+        let email_client = EmailClient::new(
+            sender,
+            PostmarkTransport::new(
+                mock_server.uri(),
+                "token".to_string().into(),
+                Duration::from_millis(200),
+            )
+            .unwrap(),
+        )
+        .with_circuit_breaker(CircuitBreaker::new(2, Duration::from_secs(60)));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        for _ in 0..2 {
+            let _ = email_client
+                .send_email(&recipient, "Subject", "<p>Body</p>", "Body")
+                .await;
+        }
+        let outcome = email_client
+            .send_email(&recipient, "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        // Assert
+        let error = assert_err!(outcome);
+        assert!(matches!(error, EmailError::CircuitOpen));
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn every_email_of_a_batch_counts_against_the_rate_limit() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let sender = SubscriberEmail::parse("newsletter@example.com".into()).unwrap();
+        let email_client = EmailClient::new(
+            sender,
+            PostmarkTransport::new(
+                mock_server.uri(),
+                "token".to_string().into(),
+                Duration::from_millis(200),
+            )
+            .unwrap(),
+        )
+        .with_rate_limiter(RateLimiter::new(10.0, 1, None).unwrap());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&mock_server)
+            .await;
+        let emails: Vec<_> = (0..3)
+            .map(|i| OutgoingEmail {
+                recipient: SubscriberEmail::parse(format!("reader{}@example.com", i)).unwrap(),
+                subject: "Subject".into(),
+                html_content: "<p>Body</p>".into(),
+                text_content: "Body".into(),
+                headers: vec![],
+            })
+            .collect();
+
+        // Act
+        let started_at = Instant::now();
+        email_client.send_batch(&emails).await;
+
+        // Assert
+        // A burst of 1, then a token every 100ms.
+        assert!(started_at.elapsed() >= Duration::from_millis(190));
+    }
+}
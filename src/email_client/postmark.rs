@@ -0,0 +1,509 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use reqwest::{Client, Response, StatusCode};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::email_client::{
+    BatchOutcome, Email, EmailError, EmailHeader, EmailTransport, FailedDelivery, SentEmail,
+};
+
+/// Postmark rejects batches with more messages than this.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Sends emails through Postmark's HTTP API.
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: SecretString,
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        authorization_token: SecretString,
+        timeout: std::time::Duration,
+    ) -> Result<Self, reqwest::Error> {
+        let http_client = Client::builder().timeout(timeout).build()?;
+        Ok(Self {
+            http_client,
+            base_url,
+            authorization_token,
+        })
+    }
+
+    async fn post<T: serde::Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<Response, EmailError> {
+        let response = self
+            .http_client
+            .post(format!("{}{}", self.base_url, path))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(body)
+            .send()
+            .await
+            .map_err(EmailError::Request)?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(rejection(response).await)
+        }
+    }
+
+    /// POST up to [`MAX_BATCH_SIZE`] emails to `/email/batch`.
+    async fn send_chunk(&self, chunk: &[Email<'_>]) -> Result<Vec<PostmarkResponse>, EmailError> {
+        let request_body: Vec<_> = chunk.iter().map(SendEmailRequest::from).collect();
+        let results = self
+            .post("/email/batch", &request_body)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse the response to a batch of emails.")?;
+        Ok(results)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<SentEmail, EmailError> {
+        let response = self.post("/email", &SendEmailRequest::from(email)).await?;
+        // The email has been accepted at this point: a body we cannot parse is not a
+        // reason to report a failure and have it sent twice.
+        let message_id = response
+            .json::<PostmarkResponse>()
+            .await
+            .ok()
+            .and_then(|response| response.message_id);
+        Ok(SentEmail { message_id })
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> BatchOutcome {
+        let mut outcome = BatchOutcome::default();
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(results) => record_results(&mut outcome, chunk, results),
+                Err(e) => {
+                    let error = Arc::new(e);
+                    outcome
+                        .failed
+                        .extend(chunk.iter().map(|email| FailedDelivery {
+                            recipient: email.to.clone(),
+                            error: error.clone(),
+                        }));
+                }
+            }
+        }
+        outcome
+    }
+}
+
+/// Turn a non-2xx response into an [`EmailError`], using Postmark's JSON error body
+/// when there is one.
+async fn rejection(response: Response) -> EmailError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    match serde_json::from_str::<PostmarkResponse>(&body) {
+        Ok(error) => EmailError::Rejected {
+            status,
+            error_code: Some(error.error_code),
+            message: error.message,
+        },
+        Err(_) => EmailError::Rejected {
+            status,
+            error_code: None,
+            message: body,
+        },
+    }
+}
+
+/// Postmark answers a batch with one result per message, in the order they were sent.
+fn record_results(outcome: &mut BatchOutcome, chunk: &[Email<'_>], results: Vec<PostmarkResponse>) {
+    let mut results = results.into_iter();
+    for email in chunk {
+        let error = match results.next() {
+            Some(result) if result.error_code == 0 => {
+                outcome.succeeded.push(email.to.clone());
+                continue;
+            }
+            // The status Postmark would have answered with, had the email been sent alone.
+            Some(result) => EmailError::Rejected {
+                status: StatusCode::UNPROCESSABLE_ENTITY,
+                error_code: Some(result.error_code),
+                message: result.message,
+            },
+            None => EmailError::UnexpectedError(anyhow::anyhow!(
+                "Postmark did not report a result for this message."
+            )),
+        };
+        outcome.failed.push(FailedDelivery {
+            recipient: email.to.clone(),
+            error: Arc::new(error),
+        });
+    }
+}
+
+/// The body Postmark answers with, for successes and failures alike.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResponse {
+    error_code: u32,
+    message: String,
+    #[serde(rename = "MessageID", default)]
+    message_id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+impl<'a> From<&Email<'a>> for SendEmailRequest<'a> {
+    fn from(email: &Email<'a>) -> Self {
+        Self {
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: email.headers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, EmailHeader, OutgoingEmail, PostmarkTransport};
+    use claims::{assert_err, assert_none, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::SecretString;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    /// Generate a random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    /// Generate a random subscriber email
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// Generate `n` emails for distinct recipients
+    fn outgoing_emails(n: usize) -> Vec<OutgoingEmail> {
+        (0..n)
+            .map(|i| OutgoingEmail {
+                recipient: SubscriberEmail::parse(format!("reader-{}@example.com", i)).unwrap(),
+                subject: subject(),
+                html_content: content(),
+                text_content: content(),
+                headers: vec![],
+            })
+            .collect()
+    }
+
+    /// Answer a batch request with an `OK` result for every message in it.
+    fn all_ok(request: &Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|_| serde_json::json!({"ErrorCode": 0, "Message": "OK"}))
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+
+    /// Get a test instance of `EmailClient`.
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            email(),
+            PostmarkTransport::new(
+                base_url,
+                SecretString::new(Faker.fake::<String>().into()),
+                std::time::Duration::from_millis(200),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_the_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        }];
+
+        Mock::given(body_partial_json(serde_json::json!({
+            "Headers": [{"Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click"}]
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_message_id_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ErrorCode": 0,
+                "Message": "OK",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        let sent = assert_ok!(outcome);
+        assert_eq!(
+            sent.message_id.as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200_without_a_body() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        let sent = assert_ok!(outcome);
+        assert_none!(sent.message_id);
+    }
+
+    #[tokio::test]
+    async fn send_email_failures_are_classified_by_status_and_error_code() {
+        let cases = [
+            (500, None, true),
+            (503, None, true),
+            (429, None, true),
+            (503, Some((100, "Maintenance")), true),
+            (401, Some((10, "Bad or missing API token")), false),
+            (422, Some((300, "Invalid email request")), false),
+            (422, Some((406, "Inactive recipient")), false),
+        ];
+
+        for (status, postmark_error, retryable) in cases {
+            // Arrange
+            let mock_server = MockServer::start().await;
+            let email_client = email_client(mock_server.uri());
+            let mut response = ResponseTemplate::new(status);
+            if let Some((error_code, message)) = postmark_error {
+                response = response.set_body_json(serde_json::json!({
+                    "ErrorCode": error_code,
+                    "Message": message
+                }));
+            }
+            Mock::given(any())
+                .respond_with(response)
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+
+            // Act
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+
+            // Assert
+            let error = assert_err!(outcome);
+            assert_eq!(error.status().map(|s| s.as_u16()), Some(status));
+            assert_eq!(error.error_code(), postmark_error.map(|(code, _)| code));
+            assert_eq!(
+                error.is_retryable(),
+                retryable,
+                "status {} with {:?}",
+                status,
+                postmark_error
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        let error = assert_err!(outcome);
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_messages_in_chunks_of_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(all_ok)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&outgoing_emails(501)).await;
+
+        // Assert
+        assert_eq!(outcome.succeeded.len(), 501);
+        assert!(outcome.failed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_messages_postmark_rejected() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let emails = outgoing_emails(3);
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK"},
+                {"ErrorCode": 406, "Message": "You tried to send to a recipient that has been marked as inactive."},
+                {"ErrorCode": 0, "Message": "OK"}
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcome.succeeded.len(), 2);
+        assert_eq!(outcome.failed.len(), 1);
+        let failure = &outcome.failed[0];
+        assert_eq!(failure.recipient.as_ref(), emails[1].recipient.as_ref());
+        assert_eq!(failure.error.error_code(), Some(406));
+        assert!(!failure.error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_message_of_a_rejected_chunk() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&outgoing_emails(3)).await;
+
+        // Assert
+        assert!(outcome.succeeded.is_empty());
+        assert_eq!(outcome.failed.len(), 3);
+        assert!(outcome.failed.iter().all(|f| f.error.is_retryable()));
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use redis::aio::ConnectionManager;
+
+/// Redis key holding the token bucket shared by every replica.
+const SHARED_BUCKET_KEY: &str = "email_client:rate_limit";
+
+/// Token bucket kept in a Redis hash, refilled according to the Redis server clock so
+/// that replicas with skewed clocks agree.
+///
+/// Returns 0 if a token was taken, otherwise how many milliseconds to wait for one.
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local rate = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local time = redis.call("TIME")
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+local state = redis.call("HMGET", KEYS[1], "tokens", "refilled_at")
+local tokens = tonumber(state[1]) or burst
+local refilled_at = tonumber(state[2]) or now
+tokens = math.min(burst, tokens + (now - refilled_at) * rate / 1000)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) * 1000 / rate)
+end
+redis.call("HSET", KEYS[1], "tokens", tostring(tokens), "refilled_at", now)
+redis.call("PEXPIRE", KEYS[1], math.ceil(burst * 1000 / rate) + 1000)
+return wait
+"#;
+
+/// Caps how many requests per second `EmailClient` sends to the provider.
+///
+/// With a Redis connection the budget is shared by every replica. If Redis cannot be
+/// reached the limiter falls back to a bucket local to this process.
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: u32,
+    local: Mutex<TokenBucket>,
+    redis: Option<ConnectionManager>,
+}
+
+impl RateLimiter {
+    /// Fails unless `requests_per_second` is a positive number and `burst` is at least
+    /// 1: with either at 0 no request could ever be sent.
+    pub fn new(
+        requests_per_second: f64,
+        burst: u32,
+        redis: Option<ConnectionManager>,
+    ) -> Result<Self, anyhow::Error> {
+        anyhow::ensure!(
+            requests_per_second.is_finite() && requests_per_second > 0.0,
+            "The rate limit must be a positive number of requests per second, got {}.",
+            requests_per_second
+        );
+        anyhow::ensure!(burst > 0, "The rate limit burst must be at least 1.");
+        Ok(Self {
+            requests_per_second,
+            burst,
+            local: Mutex::new(TokenBucket::full(burst, Instant::now())),
+            redis,
+        })
+    }
+
+    /// Wait until a request may be sent.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = match &self.redis {
+                Some(redis) => match self.take_shared_token(redis.clone()).await {
+                    Ok(wait) => wait,
+                    Err(e) => {
+                        tracing::warn!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Failed to reach the shared email rate limiter, limiting locally",
+                        );
+                        self.take_local_token()
+                    }
+                },
+                None => self.take_local_token(),
+            };
+            let Some(wait) = wait else {
+                return;
+            };
+            metrics::counter!("email_client_rate_limited_total").increment(1);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn take_local_token(&self) -> Option<Duration> {
+        self.local
+            .lock()
+            .unwrap()
+            .try_take(self.requests_per_second, self.burst, Instant::now())
+    }
+
+    async fn take_shared_token(
+        &self,
+        mut redis: ConnectionManager,
+    ) -> Result<Option<Duration>, redis::RedisError> {
+        let wait_milliseconds: u64 = redis::Script::new(TAKE_TOKEN_SCRIPT)
+            .key(SHARED_BUCKET_KEY)
+            .arg(self.requests_per_second)
+            .arg(self.burst)
+            .invoke_async(&mut redis)
+            .await?;
+        Ok((wait_milliseconds > 0).then(|| Duration::from_millis(wait_milliseconds)))
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(burst: u32, now: Instant) -> Self {
+        Self {
+            tokens: burst.into(),
+            refilled_at: now,
+        }
+    }
+
+    /// Take a token if there is one, otherwise return how long until there is.
+    fn try_take(&mut self, rate: f64, burst: u32, now: Instant) -> Option<Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst.into());
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, TokenBucket};
+    use claims::{assert_err, assert_none, assert_ok, assert_some};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_then_asks_to_wait() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::full(3, now);
+
+        for _ in 0..3 {
+            assert_none!(bucket.try_take(2.0, 3, now));
+        }
+        let wait = assert_some!(bucket.try_take(2.0, 3, now));
+        assert_eq!(wait, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time_up_to_the_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::full(2, now);
+        assert_none!(bucket.try_take(1.0, 2, now));
+        assert_none!(bucket.try_take(1.0, 2, now));
+
+        let later = now + Duration::from_secs(60);
+        assert_none!(bucket.try_take(1.0, 2, later));
+        assert_none!(bucket.try_take(1.0, 2, later));
+        assert_some!(bucket.try_take(1.0, 2, later));
+    }
+
+    #[test]
+    fn a_rate_limit_that_would_never_let_a_request_through_is_rejected() {
+        assert_ok!(RateLimiter::new(0.5, 1, None).map(|_| ()));
+        assert_err!(RateLimiter::new(0.0, 1, None).map(|_| ()));
+        assert_err!(RateLimiter::new(-1.0, 1, None).map(|_| ()));
+        assert_err!(RateLimiter::new(f64::NAN, 1, None).map(|_| ()));
+        assert_err!(RateLimiter::new(10.0, 0, None).map(|_| ()));
+    }
+}
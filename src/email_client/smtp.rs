@@ -0,0 +1,59 @@
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::email_client::{mime_message, Email, EmailError, EmailTransport, SentEmail};
+
+/// How the connection to the SMTP server is secured.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plain text, e.g. a local relay such as MailHog.
+    None,
+    /// Upgrade a plain text connection with `STARTTLS`.
+    #[default]
+    Starttls,
+    /// TLS from the first byte (SMTPS).
+    Tls,
+}
+
+/// Sends emails through an SMTP relay.
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, SecretString)>,
+        timeout: std::time::Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = match tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        }
+        .port(port)
+        .timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder =
+                builder.credentials(Credentials::new(username, password.expose_secret().into()));
+        }
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<SentEmail, EmailError> {
+        let message = mime_message(email)?;
+        self.mailer.send(message).await.map_err(EmailError::Smtp)?;
+        Ok(SentEmail::default())
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use arc_swap::ArcSwapOption;
+use minijinja::{path_loader, Environment, ErrorKind, Value};
+use sqlx::{PgPool, Row};
+
+use crate::routes::error_chain_fmt;
+
+/// Line width of the plain-text part generated from the HTML one.
+const TEXT_WIDTH: usize = 78;
+
+/// How long templates are reused before the `email_templates` table is read again, i.e.
+/// how long an edit takes to show up in the emails sent.
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Named email templates, rendered with [minijinja](https://docs.rs/minijinja).
+///
+/// A template named `confirmation` is made of up to three files:
+/// - `confirmation/subject.txt`;
+/// - `confirmation/body.html`, auto-escaped, usually extending a layout from `layouts/`;
+/// - `confirmation/body.txt`, optional: the plain-text part is generated from the
+///   HTML when it is missing.
+///
+/// Files are read from `directory`. Rows of the `email_templates` table take precedence
+/// over the file with the same path, so templates can be edited without a deploy.
+///
+/// Templates are loaded once and reused for [`CACHE_TTL`], by every clone.
+#[derive(Clone)]
+pub struct EmailTemplates {
+    directory: PathBuf,
+    pool: PgPool,
+    cache: Arc<ArcSwapOption<CachedEnvironment>>,
+}
+
+struct CachedEnvironment {
+    environment: Environment<'static>,
+    loaded_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum TemplateError {
+    #[error("There is no email template named `{0}`")]
+    NotFound(String),
+    #[error("Failed to render the email template `{0}`")]
+    Render(String, #[source] minijinja::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl EmailTemplates {
+    pub fn new(directory: PathBuf, pool: PgPool) -> Self {
+        Self {
+            directory,
+            pool,
+            cache: Arc::new(ArcSwapOption::empty()),
+        }
+    }
+
+    #[tracing::instrument(name = "Render an email template", skip(self, context))]
+    pub async fn render(&self, name: &str, context: Value) -> Result<RenderedEmail, TemplateError> {
+        let cached = match self.cache.load_full() {
+            Some(cached) if cached.loaded_at.elapsed() < CACHE_TTL => cached,
+            _ => self.reload().await?,
+        };
+        render(&cached.environment, name, &context)
+    }
+
+    /// Read the templates again on the next render, e.g. to preview an edit right away.
+    pub fn invalidate(&self) {
+        self.cache.store(None);
+    }
+
+    async fn reload(&self) -> Result<Arc<CachedEnvironment>, anyhow::Error> {
+        let overrides = get_template_overrides(&self.pool)
+            .await
+            .context("Failed to load the email templates stored in the database.")?;
+        let cached = Arc::new(CachedEnvironment {
+            environment: environment(self.directory.clone(), overrides),
+            loaded_at: Instant::now(),
+        });
+        self.cache.store(Some(cached.clone()));
+        Ok(cached)
+    }
+}
+
+fn environment(directory: PathBuf, overrides: HashMap<String, String>) -> Environment<'static> {
+    let from_disk = path_loader(directory);
+    let mut environment = Environment::new();
+    environment.set_loader(move |path| match overrides.get(path) {
+        Some(source) => Ok(Some(source.clone())),
+        None => from_disk(path),
+    });
+    environment
+}
+
+fn render(
+    environment: &Environment<'_>,
+    name: &str,
+    context: &Value,
+) -> Result<RenderedEmail, TemplateError> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(TemplateError::NotFound(name.into()));
+    }
+    let render_part = |part: &str| -> Result<Option<String>, TemplateError> {
+        let template = match environment.get_template(&format!("{}/{}", name, part)) {
+            Ok(template) => template,
+            Err(e) if e.kind() == ErrorKind::TemplateNotFound => return Ok(None),
+            Err(e) => return Err(TemplateError::Render(name.into(), e)),
+        };
+        template
+            .render(context)
+            .map(Some)
+            .map_err(|e| TemplateError::Render(name.into(), e))
+    };
+
+    let subject = render_part("subject.txt")?;
+    let html = render_part("body.html")?;
+    let (Some(subject), Some(html)) = (subject, html) else {
+        return Err(TemplateError::NotFound(name.into()));
+    };
+    let text = match render_part("body.txt")? {
+        Some(text) => text,
+        None => html2text::from_read(html.as_bytes(), TEXT_WIDTH)
+            .context("Failed to generate the plain-text part from the HTML one.")?,
+    };
+    Ok(RenderedEmail {
+        // A header: no line breaks allowed.
+        subject: subject.split_whitespace().collect::<Vec<_>>().join(" "),
+        html,
+        text,
+    })
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_template_overrides(pool: &PgPool) -> Result<HashMap<String, String>, sqlx::Error> {
+    let rows = sqlx::query(r#"SELECT path, source FROM email_templates"#)
+        .fetch_all(pool)
+        .await?;
+    rows.into_iter()
+        .map(|row| Ok((row.try_get("path")?, row.try_get("source")?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{environment, render, EmailTemplates, TemplateError};
+    use claims::{assert_err, assert_ok};
+    use minijinja::{context, Value};
+    use sqlx::PgPool;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    fn templates_directory() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/templates")
+    }
+
+    fn overrides(templates: &[(&str, &str)]) -> HashMap<String, String> {
+        templates
+            .iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn variables_are_escaped_in_html_but_not_in_text() {
+        let environment = environment(
+            templates_directory(),
+            overrides(&[
+                ("greeting/subject.txt", "Hi {{ name }}"),
+                ("greeting/body.html", "<p>Hi {{ name }}</p>"),
+                ("greeting/body.txt", "Hi {{ name }}"),
+            ]),
+        );
+
+        let email = assert_ok!(render(
+            &environment,
+            "greeting",
+            &context! { name => "<Tom & Jerry>" }
+        ));
+
+        assert_eq!(email.subject, "Hi <Tom & Jerry>");
+        assert_eq!(email.html, "<p>Hi &lt;Tom &amp; Jerry&gt;</p>");
+        assert_eq!(email.text, "Hi <Tom & Jerry>");
+    }
+
+    #[test]
+    fn the_text_part_is_generated_from_the_html_when_missing() {
+        let environment = environment(
+            templates_directory(),
+            overrides(&[
+                ("greeting/subject.txt", "Hi"),
+                (
+                    "greeting/body.html",
+                    "<p>Visit <a href=\"https://example.com\">us</a></p>",
+                ),
+            ]),
+        );
+
+        let email = assert_ok!(render(&environment, "greeting", &Value::UNDEFINED));
+
+        assert!(email.text.contains("Visit"));
+        assert!(email.text.contains("https://example.com"));
+    }
+
+    #[test]
+    fn templates_can_extend_the_layouts_on_disk() {
+        let environment = environment(
+            templates_directory(),
+            overrides(&[
+                ("greeting/subject.txt", "Hi"),
+                (
+                    "greeting/body.html",
+                    "{% extends \"layouts/base.html\" %}{% block content %}<p>Hi</p>{% endblock %}",
+                ),
+            ]),
+        );
+
+        let email = assert_ok!(render(&environment, "greeting", &Value::UNDEFINED));
+
+        assert!(email.html.contains("<html"));
+        assert!(email.html.contains("<p>Hi</p>"));
+    }
+
+    #[test]
+    fn the_shipped_templates_render() {
+        let environment = environment(templates_directory(), HashMap::new());
+
+        let confirmation = assert_ok!(render(
+            &environment,
+            "confirmation",
+            &context! {
+                subscriber_name => "Ursula",
+                confirmation_link => "https://example.com/confirm",
+            }
+        ));
+        assert!(confirmation.html.contains("Ursula"));
+        // Generated from the HTML, where the link is escaped.
+        assert!(confirmation.text.contains("https://example.com/confirm"));
+
+        let issue = assert_ok!(render(
+            &environment,
+            "newsletter_issue",
+            &context! {
+                issue_title => "Issue #1",
+                issue_html => Value::from_safe_string("<h1>News</h1>".into()),
+                issue_text => "News",
+                unsubscribe_link => "https://example.com/unsubscribe",
+            }
+        ));
+        assert_eq!(issue.subject, "Issue #1");
+        assert!(issue.html.contains("<h1>News</h1>"));
+        assert!(issue.text.contains("https://example.com/unsubscribe"));
+    }
+
+    #[test]
+    fn unknown_or_malformed_names_are_not_found() {
+        let environment = environment(templates_directory(), HashMap::new());
+
+        for name in ["does_not_exist", "../configuration", ""] {
+            let outcome = render(&environment, name, &Value::UNDEFINED);
+            assert!(matches!(assert_err!(outcome), TemplateError::NotFound(_)));
+        }
+    }
+
+    async fn set_subject(pool: &PgPool, subject: &str) {
+        sqlx::query(
+            r#"
+            INSERT INTO email_templates (path, source) VALUES ('confirmation/subject.txt', $1)
+            ON CONFLICT (path) DO UPDATE SET source = EXCLUDED.source, updated_at = now()
+            "#,
+        )
+        .bind(subject)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn stored_templates_are_cached_until_invalidated(pool: PgPool) {
+        let templates = EmailTemplates::new(templates_directory(), pool.clone());
+        set_subject(&pool, "Before").await;
+        let subject = || async {
+            assert_ok!(
+                templates
+                    .clone()
+                    .render("confirmation", Value::UNDEFINED)
+                    .await
+            )
+            .subject
+        };
+        assert_eq!(subject().await, "Before");
+
+        set_subject(&pool, "After").await;
+        assert_eq!(subject().await, "Before");
+
+        templates.invalidate();
+        assert_eq!(subject().await, "After");
+    }
+}
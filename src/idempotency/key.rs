@@ -0,0 +1,58 @@
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub const MAX_LENGTH: usize = 50;
+}
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.is_empty() {
+            return Err("The idempotency key cannot be empty.".into());
+        }
+        if s.len() >= Self::MAX_LENGTH {
+            return Err(format!(
+                "The idempotency key must be shorter than {} characters.",
+                Self::MAX_LENGTH
+            ));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl From<IdempotencyKey> for String {
+    fn from(k: IdempotencyKey) -> Self {
+        k.0
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(IdempotencyKey::try_from("".to_string()));
+    }
+
+    #[test]
+    fn a_key_of_max_length_is_rejected() {
+        let key = "a".repeat(IdempotencyKey::MAX_LENGTH);
+        assert_err!(IdempotencyKey::try_from(key));
+    }
+
+    #[test]
+    fn a_key_shorter_than_max_length_is_accepted() {
+        let key = "a".repeat(IdempotencyKey::MAX_LENGTH - 1);
+        assert_ok!(IdempotencyKey::try_from(key));
+    }
+}
@@ -0,0 +1,196 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::idempotency::IdempotencyKey;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+#[derive(sqlx::FromRow)]
+struct SavedResponse {
+    response_status_code: i16,
+    response_headers: Vec<HeaderPairRecord>,
+    response_body: Vec<u8>,
+}
+
+pub enum NextAction {
+    /// The request is being processed for the first time; the transaction holds
+    /// the idempotency row until the response is saved.
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(Response),
+}
+
+/// Claim `idempotency_key` on behalf of `user_id` for the current request.
+///
+/// If another request with the same key is still being processed, the insert blocks
+/// on the primary key until that request's transaction commits; we then replay the
+/// response it saved.
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_inserted_rows = sqlx::query(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(idempotency_key.as_ref())
+    .bind(Utc::now())
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+pub async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<Response>, anyhow::Error> {
+    let saved_response: Option<SavedResponse> = sqlx::query_as(
+        r#"
+        SELECT
+            response_status_code,
+            response_headers,
+            response_body
+        FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2 AND
+            response_status_code IS NOT NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(idempotency_key.as_ref())
+    .fetch_optional(pool)
+    .await?;
+    let Some(saved_response) = saved_response else {
+        return Ok(None);
+    };
+
+    let status_code = StatusCode::from_u16(saved_response.response_status_code.try_into()?)?;
+    let mut response = (status_code, saved_response.response_body).into_response();
+    let headers = response.headers_mut();
+    headers.clear();
+    for HeaderPairRecord { name, value } in saved_response.response_headers {
+        headers.append(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+    }
+    Ok(Some(response))
+}
+
+/// Store `response` against `idempotency_key`, commit the transaction opened by
+/// [`try_processing`] and hand the response back to the caller.
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    response: Response,
+) -> Result<Response, anyhow::Error> {
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await?;
+    let status_code = parts.status.as_u16() as i16;
+    let headers: Vec<HeaderPairRecord> = parts
+        .headers
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect();
+    sqlx::query(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(idempotency_key.as_ref())
+    .bind(status_code)
+    .bind(headers)
+    .bind(body.as_ref())
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{save_response, try_processing, NextAction};
+    use crate::idempotency::IdempotencyKey;
+    use axum::{body::to_bytes, http::StatusCode, response::IntoResponse};
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    async fn add_user(pool: &PgPool, username: &str) -> Uuid {
+        let user_id = Uuid::new_v4();
+        sqlx::query(
+            r#"INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, 'unused')"#,
+        )
+        .bind(user_id)
+        .bind(username)
+        .execute(pool)
+        .await
+        .unwrap();
+        user_id
+    }
+
+    async fn publish(pool: &PgPool, user_id: Uuid, body: &'static str) -> String {
+        let key = IdempotencyKey::try_from("publish-issue-1".to_string()).unwrap();
+        let response = match try_processing(pool, &key, user_id).await.unwrap() {
+            NextAction::StartProcessing(transaction) => {
+                let response = (StatusCode::OK, body).into_response();
+                save_response(transaction, &key, user_id, response)
+                    .await
+                    .unwrap()
+            }
+            NextAction::ReturnSavedResponse(response) => response,
+        };
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn idempotency_keys_are_scoped_per_user(pool: PgPool) {
+        let ada = add_user(&pool, "ada").await;
+        let grace = add_user(&pool, "grace").await;
+
+        assert_eq!(publish(&pool, ada, "ada's issue").await, "ada's issue");
+        // The same key, from someone else: a request of its own.
+        assert_eq!(
+            publish(&pool, grace, "grace's issue").await,
+            "grace's issue"
+        );
+        // A retry: the saved response is replayed.
+        assert_eq!(publish(&pool, ada, "ada's retry").await, "ada's issue");
+    }
+}
@@ -0,0 +1,781 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use minijinja::{context, Value};
+use sqlx::{Acquire, PgPool, Postgres, Row, Transaction};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::SubscriberEmail,
+    email_client::{EmailError, OutgoingEmail, SharedEmailClient},
+    email_templates::EmailTemplates,
+    jobs::{Backoff, ExecutionOutcome, RetryPolicy},
+    routes::{
+        list_unsubscribe_headers, open_tracking_link, track_clicks, unsubscribe_link,
+        TrackingToken, UnsubscribeToken,
+    },
+    startup::{get_connection_pool, ApplicationBaseUrl, HmacSecret},
+    suppression::is_suppressed,
+};
+
+/// How long the worker sleeps when there is nothing to deliver.
+const EMPTY_QUEUE_BACKOFF: Duration = Duration::from_secs(10);
+/// How long the worker sleeps after failing to talk to the database.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+/// How many queued emails the worker sends at once, see [`EmailClient::send_batch`].
+///
+/// [`EmailClient::send_batch`]: crate::email_client::EmailClient::send_batch
+const BATCH_SIZE: i64 = 100;
+
+const RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    backoff: Backoff::ExponentialWithJitter {
+        base: Duration::from_secs(5),
+        max: Duration::from_secs(10 * 60),
+    },
+};
+
+/// Sends newsletter issues, in batches of `issue_delivery_queue` rows (i.e. recipients).
+pub struct IssueDeliveryWorker {
+    pool: PgPool,
+    email_client: SharedEmailClient,
+    email_templates: EmailTemplates,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+    unsubscribe_link_ttl: Option<chrono::Duration>,
+}
+
+impl IssueDeliveryWorker {
+    pub fn build(configuration: Settings, email_client: SharedEmailClient) -> Self {
+        let pool = get_connection_pool(&configuration.database);
+        let unsubscribe_link_ttl = configuration.application.unsubscribe_link_ttl();
+        Self {
+            email_templates: EmailTemplates::new(
+                configuration.application.templates_directory,
+                pool.clone(),
+            ),
+            pool,
+            email_client,
+            base_url: ApplicationBaseUrl(configuration.application.base_url),
+            hmac_secret: HmacSecret(configuration.application.hmac_secret),
+            unsubscribe_link_ttl,
+        }
+    }
+
+    /// Deliver queued emails until `shutdown` is cancelled.
+    ///
+    /// A delivery that is in flight when shutdown starts is allowed to finish.
+    pub async fn run_until_stopped(self, shutdown: CancellationToken) -> Result<(), anyhow::Error> {
+        while !shutdown.is_cancelled() {
+            let backoff = match self.try_execute_task().await {
+                Ok(ExecutionOutcome::EmptyQueue) => EMPTY_QUEUE_BACKOFF,
+                Err(_) => ERROR_BACKOFF,
+                Ok(ExecutionOutcome::TaskCompleted) => continue,
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = shutdown.cancelled() => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i32,
+}
+
+#[derive(Clone)]
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    tracking_enabled: bool,
+    /// How many rows of `newsletter_issue_links` the issue has.
+    link_count: i64,
+}
+
+impl IssueDeliveryWorker {
+    /// Deliver up to [`BATCH_SIZE`] queued emails, one batch per issue.
+    #[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+    pub async fn try_execute_task(&self) -> Result<ExecutionOutcome, anyhow::Error> {
+        let (mut transaction, tasks) = dequeue_tasks(&self.pool).await?;
+        if tasks.is_empty() {
+            return Ok(ExecutionOutcome::EmptyQueue);
+        }
+        tracing::Span::current().record("n_tasks", tasks.len());
+
+        let mut issues = HashMap::new();
+        let mut batches: HashMap<Uuid, Vec<(Task, OutgoingEmail)>> = HashMap::new();
+        for task in tasks {
+            // Within a savepoint, so that a task that fails halfway leaves nothing
+            // behind and does not abort the whole transaction.
+            let mut savepoint = Acquire::begin(&mut *transaction).await?;
+            match self.prepare_email(&mut savepoint, &task, &mut issues).await {
+                Ok(email) => {
+                    savepoint.commit().await?;
+                    if let Some(email) = email {
+                        batches
+                            .entry(task.newsletter_issue_id)
+                            .or_default()
+                            .push((task, email));
+                    }
+                }
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    retry_or_give_up(&mut transaction, &task, &e, true).await?;
+                }
+            }
+        }
+        for batch in batches.into_values() {
+            self.send_batch(&mut transaction, batch).await?;
+        }
+        transaction.commit().await?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+
+    /// The email to send for `task`, or `None` if the recipient is skipped, in which
+    /// case the task is deleted.
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            newsletter_issue_id = %task.newsletter_issue_id,
+            subscriber_email = %task.subscriber_email,
+            n_retries = task.n_retries
+        )
+    )]
+    async fn prepare_email(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        task: &Task,
+        issues: &mut HashMap<Uuid, NewsletterIssue>,
+    ) -> Result<Option<OutgoingEmail>, anyhow::Error> {
+        let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => email,
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid",
+                );
+                delete_task(transaction, task).await?;
+                return Ok(None);
+            }
+        };
+        let Some(subscriber) = get_confirmed_subscriber(&self.pool, &email).await? else {
+            tracing::info!("Skipping a recipient that is no longer a confirmed subscriber");
+            delete_task(transaction, task).await?;
+            return Ok(None);
+        };
+
+        if is_suppressed(&self.pool, &email).await? {
+            tracing::info!("Skipping a recipient that is on the suppression list");
+            record_suppressed_delivery(transaction, task.newsletter_issue_id, subscriber.id)
+                .await?;
+            delete_task(transaction, task).await?;
+            return Ok(None);
+        }
+
+        let mut issue = match issues.get(&task.newsletter_issue_id) {
+            Some(issue) => issue.clone(),
+            None => {
+                let issue = get_issue(&self.pool, task.newsletter_issue_id).await?;
+                issues.insert(task.newsletter_issue_id, issue.clone());
+                issue
+            }
+        };
+        let open_tracking_link = issue.tracking_enabled.then(|| {
+            self.track_clicks(&mut issue, task.newsletter_issue_id, subscriber.id);
+            let token = TrackingToken::open(task.newsletter_issue_id, subscriber.id);
+            open_tracking_link(&self.base_url, &self.hmac_secret, &token)
+        });
+        let expires_at = self.unsubscribe_link_ttl.map(|ttl| Utc::now() + ttl);
+        let token = UnsubscribeToken::new(subscriber.id, expires_at);
+        let unsubscribe_link = unsubscribe_link(&self.base_url, &self.hmac_secret, &token);
+        let rendered = self
+            .email_templates
+            .render(
+                "newsletter_issue",
+                context! {
+                    subscriber_name => subscriber.name,
+                    issue_title => issue.title,
+                    // Written by an authenticated admin, as HTML.
+                    issue_html => Value::from_safe_string(issue.html_content),
+                    issue_text => issue.text_content,
+                    unsubscribe_link => &unsubscribe_link,
+                    open_tracking_link => open_tracking_link,
+                },
+            )
+            .await
+            .context("Failed to render the newsletter issue.")?;
+        Ok(Some(OutgoingEmail {
+            recipient: email,
+            subject: rendered.subject,
+            html_content: rendered.html,
+            text_content: rendered.text,
+            headers: list_unsubscribe_headers(&unsubscribe_link),
+        }))
+    }
+
+    /// Send the emails of `batch`, all for the same issue, then delete the tasks of
+    /// the ones that went through and retry the others.
+    async fn send_batch(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        batch: Vec<(Task, OutgoingEmail)>,
+    ) -> Result<(), anyhow::Error> {
+        let (tasks, emails): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        let outcome = self.email_client.load().send_batch(&emails).await;
+        // An issue is queued once per subscriber, so recipients are unique.
+        let errors: HashMap<&str, &EmailError> = outcome
+            .failed
+            .iter()
+            .map(|failure| (failure.recipient.as_ref(), &*failure.error))
+            .collect();
+        for (task, email) in tasks.iter().zip(&emails) {
+            let error = errors.get(email.recipient.as_ref()).copied();
+            settle_task(transaction, task, error).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Delete the task of an email that was sent, or retry it if it was not.
+async fn settle_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    error: Option<&EmailError>,
+) -> Result<(), anyhow::Error> {
+    match error {
+        Some(e) => retry_or_give_up(transaction, task, e, e.is_retryable()).await,
+        None => Ok(delete_task(transaction, task).await?),
+    }
+}
+
+/// Retry the task later, as long as it failed for a reason that may go away and it
+/// has attempts left. Otherwise delete it: the recipient does not get the issue.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = %task.newsletter_issue_id,
+        subscriber_email = %task.subscriber_email,
+        n_retries = task.n_retries
+    )
+)]
+async fn retry_or_give_up<E>(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    e: &E,
+    retryable: bool,
+) -> Result<(), anyhow::Error>
+where
+    E: std::fmt::Debug + std::fmt::Display,
+{
+    let n_retries = task.n_retries.try_into().unwrap_or(u32::MAX);
+    if retryable && RETRY_POLICY.should_retry(n_retries) {
+        let delay = RETRY_POLICY.delay(n_retries);
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            retry_in_milliseconds = delay.as_millis() as u64,
+            "Failed to deliver issue to a confirmed subscriber. Retrying later.",
+        );
+        return schedule_retry(transaction, task, delay).await;
+    }
+    tracing::error!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        "Failed to deliver issue to a confirmed subscriber. Giving up.",
+    );
+    delete_task(transaction, task).await?;
+    Ok(())
+}
+
+impl IssueDeliveryWorker {
+    /// Point the links of `issue` to the click-tracking endpoint, on behalf of
+    /// `subscriber_id`.
+    fn track_clicks(&self, issue: &mut NewsletterIssue, issue_id: Uuid, subscriber_id: Uuid) {
+        let track_clicks = |content: &str| {
+            track_clicks(
+                content,
+                &self.base_url,
+                &self.hmac_secret,
+                issue_id,
+                subscriber_id,
+                issue.link_count as i32,
+            )
+        };
+        issue.html_content = track_clicks(&issue.html_content);
+        issue.text_content = track_clicks(&issue.text_content);
+    }
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, name FROM subscriptions
+        WHERE lower(email) = lower($1) AND status = 'confirmed'
+        "#,
+    )
+    .bind(email.as_ref())
+    .fetch_optional(pool)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    Ok(Some(ConfirmedSubscriber {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+    }))
+}
+
+/// Record, in place of a delivery, that the issue was not sent to the subscriber.
+#[tracing::instrument(skip(transaction))]
+async fn record_suppressed_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO newsletter_events (newsletter_issue_id, subscriber_id, kind, occurred_at)
+        VALUES ($1, $2, 'suppressed', now())
+        "#,
+    )
+    .bind(newsletter_issue_id)
+    .bind(subscriber_id)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Lock up to [`BATCH_SIZE`] tasks that are due, until the transaction ends.
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    pool: &PgPool,
+) -> Result<(Transaction<'static, Postgres>, Vec<Task>), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let rows = sqlx::query(
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+    )
+    .bind(BATCH_SIZE)
+    .fetch_all(&mut *transaction)
+    .await?;
+    let tasks = rows
+        .iter()
+        .map(|row| {
+            Ok(Task {
+                newsletter_issue_id: row.try_get("newsletter_issue_id")?,
+                subscriber_email: row.try_get("subscriber_email")?,
+                n_retries: row.try_get("n_retries")?,
+            })
+        })
+        .collect::<Result<_, sqlx::Error>>()?;
+    Ok((transaction, tasks))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+    )
+    .bind(task.newsletter_issue_id)
+    .bind(&task.subscriber_email)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1,
+            execute_after = $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+    )
+    .bind(task.newsletter_issue_id)
+    .bind(&task.subscriber_email)
+    .bind(execute_after)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            title,
+            text_content,
+            html_content,
+            tracking_enabled,
+            (
+                SELECT COUNT(*)
+                FROM newsletter_issue_links
+                WHERE newsletter_issue_links.newsletter_issue_id = $1
+            ) AS link_count
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+    )
+    .bind(issue_id)
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue.")?;
+    Ok(NewsletterIssue {
+        title: row.try_get("title")?,
+        text_content: row.try_get("text_content")?,
+        html_content: row.try_get("html_content")?,
+        tracking_enabled: row.try_get("tracking_enabled")?,
+        link_count: row.try_get("link_count")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::Utc;
+    use secrecy::SecretString;
+    use sqlx::{PgPool, Row};
+    use uuid::Uuid;
+    use wiremock::matchers::{any, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{IssueDeliveryWorker, RETRY_POLICY};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, PostmarkTransport, SharedEmailClient};
+    use crate::email_templates::EmailTemplates;
+    use crate::jobs::ExecutionOutcome;
+    use crate::startup::{ApplicationBaseUrl, HmacSecret};
+    use crate::suppression::{self, SuppressionEntry, SuppressionReason};
+
+    fn worker(pool: &PgPool, mock_server: &MockServer) -> IssueDeliveryWorker {
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            PostmarkTransport::new(
+                mock_server.uri(),
+                SecretString::from("token"),
+                std::time::Duration::from_millis(200),
+            )
+            .unwrap(),
+        );
+        IssueDeliveryWorker {
+            pool: pool.clone(),
+            email_client: SharedEmailClient::new(email_client),
+            email_templates: EmailTemplates::new(
+                PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/templates"),
+                pool.clone(),
+            ),
+            base_url: ApplicationBaseUrl("http://localhost:8000".into()),
+            hmac_secret: HmacSecret(SecretString::from("secret")),
+            unsubscribe_link_ttl: None,
+        }
+    }
+
+    async fn add_subscriber(pool: &PgPool, email: &str, status: &str) {
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'Ursula', now(), $3)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(email)
+        .bind(status)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    /// Publish an issue, queued for `recipients`, `n_retries` times retried already.
+    async fn publish(pool: &PgPool, recipients: &[&str], n_retries: i32) -> Uuid {
+        let issue_id = Uuid::new_v4();
+        sqlx::query(
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id, title, text_content, html_content, published_at
+            )
+            VALUES ($1, 'Issue #1', 'Hi!', '<p>Hi!</p>', now())
+            "#,
+        )
+        .bind(issue_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        for recipient in recipients {
+            sqlx::query(
+                r#"
+                INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, n_retries)
+                VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(issue_id)
+            .bind(recipient)
+            .bind(n_retries)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        issue_id
+    }
+
+    /// `(subscriber_email, n_retries, due)` for every queued task.
+    async fn queue(pool: &PgPool) -> Vec<(String, i32, bool)> {
+        sqlx::query(
+            r#"
+            SELECT subscriber_email, n_retries, execute_after <= $1 AS due
+            FROM issue_delivery_queue
+            ORDER BY subscriber_email
+            "#,
+        )
+        .bind(Utc::now())
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| {
+            (
+                row.get("subscriber_email"),
+                row.get("n_retries"),
+                row.get("due"),
+            )
+        })
+        .collect()
+    }
+
+    /// Answer a batch with Postmark's result for each of `error_codes`, 0 being `OK`.
+    fn batch_results(error_codes: &[u32]) -> ResponseTemplate {
+        let results: Vec<_> = error_codes
+            .iter()
+            .map(|code| serde_json::json!({"ErrorCode": code, "Message": "Result"}))
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+
+    async fn execute(worker: &IssueDeliveryWorker) {
+        let outcome = worker.try_execute_task().await.unwrap();
+        assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+    }
+
+    #[sqlx::test]
+    async fn delivered_emails_are_removed_from_the_queue(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .respond_with(batch_results(&[0, 0]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "ada@example.com", "confirmed").await;
+        add_subscriber(&pool, "grace@example.com", "confirmed").await;
+        publish(&pool, &["ada@example.com", "grace@example.com"], 0).await;
+        let worker = worker(&pool, &mock_server);
+
+        execute(&worker).await;
+
+        assert!(queue(&pool).await.is_empty());
+        let outcome = worker.try_execute_task().await.unwrap();
+        assert!(matches!(outcome, ExecutionOutcome::EmptyQueue));
+    }
+
+    #[sqlx::test]
+    async fn retryable_failures_are_retried_later(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "ada@example.com", "confirmed").await;
+        publish(&pool, &["ada@example.com"], 0).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        assert_eq!(
+            queue(&pool).await,
+            vec![("ada@example.com".into(), 1, false)]
+        );
+    }
+
+    #[sqlx::test]
+    async fn only_the_recipients_that_failed_are_retried(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        // Postmark's `ErrorCode` while down for maintenance, which is worth retrying.
+        Mock::given(path("/email/batch"))
+            .respond_with(batch_results(&[0, 100]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "ada@example.com", "confirmed").await;
+        add_subscriber(&pool, "grace@example.com", "confirmed").await;
+        publish(&pool, &["ada@example.com", "grace@example.com"], 0).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        let queue = queue(&pool).await;
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].1, 1);
+    }
+
+    #[sqlx::test]
+    async fn permanent_failures_are_given_up_on(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        // An inactive recipient.
+        Mock::given(path("/email/batch"))
+            .respond_with(batch_results(&[406]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "ada@example.com", "confirmed").await;
+        publish(&pool, &["ada@example.com"], 0).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        assert!(queue(&pool).await.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn retryable_failures_are_given_up_on_after_the_last_attempt(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "ada@example.com", "confirmed").await;
+        let last_attempt = RETRY_POLICY.max_attempts as i32 - 1;
+        publish(&pool, &["ada@example.com"], last_attempt).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        assert!(queue(&pool).await.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn a_failure_before_sending_is_retried_later(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(batch_results(&[0]))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        sqlx::query(
+            r#"
+            INSERT INTO email_templates (path, source)
+            VALUES ('newsletter_issue/subject.txt', '{{ unclosed')
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        add_subscriber(&pool, "ada@example.com", "confirmed").await;
+        publish(&pool, &["ada@example.com"], 0).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        assert_eq!(
+            queue(&pool).await,
+            vec![("ada@example.com".into(), 1, false)]
+        );
+    }
+
+    #[sqlx::test]
+    async fn recipients_with_an_invalid_stored_email_are_skipped(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(batch_results(&[0]))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "not-an-email", "confirmed").await;
+        publish(&pool, &["not-an-email"], 0).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        assert!(queue(&pool).await.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn recipients_that_are_no_longer_confirmed_are_skipped(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(batch_results(&[0]))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "ada@example.com", "unsubscribed").await;
+        publish(&pool, &["ada@example.com", "grace@example.com"], 0).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        assert!(queue(&pool).await.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn suppressed_recipients_are_skipped_and_recorded(pool: PgPool) {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(batch_results(&[0]))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        add_subscriber(&pool, "ada@example.com", "confirmed").await;
+        let entry = SuppressionEntry::parse("example.com").unwrap();
+        suppression::suppress(&pool, &entry, SuppressionReason::Manual)
+            .await
+            .unwrap();
+        let issue_id = publish(&pool, &["ada@example.com"], 0).await;
+
+        execute(&worker(&pool, &mock_server)).await;
+
+        assert!(queue(&pool).await.is_empty());
+        let kind: String =
+            sqlx::query_scalar("SELECT kind FROM newsletter_events WHERE newsletter_issue_id = $1")
+                .bind(issue_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(kind, "suppressed");
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::jobs::JobHandler;
+
+/// Deletes subscribers that never confirmed their subscription, along with their tokens.
+pub struct CleanupUnconfirmedSubscribers {
+    pool: PgPool,
+}
+
+#[derive(serde::Deserialize)]
+struct Payload {
+    /// Only subscribers that signed up at least this long ago are deleted.
+    max_age_hours: i64,
+}
+
+impl CleanupUnconfirmedSubscribers {
+    pub const JOB_TYPE: &'static str = "cleanup_unconfirmed_subscribers";
+
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl JobHandler for CleanupUnconfirmedSubscribers {
+    fn job_type(&self) -> &'static str {
+        Self::JOB_TYPE
+    }
+
+    #[tracing::instrument(name = "Clean up unconfirmed subscribers", skip_all)]
+    async fn handle(&self, payload: serde_json::Value) -> Result<(), anyhow::Error> {
+        let payload: Payload =
+            serde_json::from_value(payload).context("Invalid cleanup job payload.")?;
+        let cutoff = chrono::Duration::try_hours(payload.max_age_hours)
+            .and_then(|max_age| Utc::now().checked_sub_signed(max_age))
+            .context("max_age_hours is out of range.")?;
+        let result = sqlx::query(
+            r#"
+            WITH stale AS (
+                SELECT id FROM subscriptions
+                WHERE status = 'pending_confirmation' AND subscribed_at < $1
+            ), deleted_tokens AS (
+                DELETE FROM subscription_tokens
+                WHERE subscriber_id IN (SELECT id FROM stale)
+            )
+            DELETE FROM subscriptions
+            WHERE id IN (SELECT id FROM stale)
+            "#,
+        )
+        .bind(cutoff)
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete unconfirmed subscribers.")?;
+        tracing::info!(
+            n_deleted = result.rows_affected(),
+            "Deleted unconfirmed subscribers"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::CleanupUnconfirmedSubscribers;
+    use crate::jobs::JobHandler;
+
+    #[sqlx::test]
+    async fn an_out_of_range_max_age_is_an_error(pool: PgPool) {
+        let job = CleanupUnconfirmedSubscribers::new(pool);
+        for max_age_hours in [i64::MAX, i64::MIN] {
+            let payload = serde_json::json!({ "max_age_hours": max_age_hours });
+            assert!(job.handle(payload).await.is_err());
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// A job that failed on its last allowed attempt.
+#[derive(serde::Serialize, sqlx::FromRow)]
+pub struct DeadLetterJob {
+    pub id: Uuid,
+    pub job_type: String,
+    pub payload: serde_json::Value,
+    pub n_retries: i32,
+    /// The error chain of the last failed attempt.
+    pub last_error: String,
+    pub created_at: DateTime<Utc>,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Move a job out of the queue and into the dead-letter table.
+#[tracing::instrument(skip(transaction, error))]
+pub(crate) async fn dead_letter_job(
+    transaction: &mut Transaction<'_, Postgres>,
+    job_id: Uuid,
+    error: &anyhow::Error,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        WITH failed AS (
+            DELETE FROM jobs
+            WHERE id = $1
+            RETURNING id, job_type, payload, n_retries, created_at
+        )
+        INSERT INTO dead_letter_jobs (id, job_type, payload, n_retries, last_error, created_at)
+        SELECT id, job_type, payload, n_retries, $2, created_at
+        FROM failed
+        "#,
+    )
+    .bind(job_id)
+    .bind(format!("{:?}", error))
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "List dead-lettered jobs", skip(pool))]
+pub async fn list_dead_letter_jobs(pool: &PgPool) -> Result<Vec<DeadLetterJob>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT id, job_type, payload, n_retries, last_error, created_at, failed_at
+        FROM dead_letter_jobs
+        ORDER BY failed_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Put a dead-lettered job back in the queue with a fresh retry budget.
+///
+/// Returns `false` if there is no dead-lettered job with the given id.
+#[tracing::instrument(name = "Requeue dead-lettered job", skip(pool))]
+pub async fn requeue_dead_letter_job(pool: &PgPool, job_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        WITH requeued AS (
+            DELETE FROM dead_letter_jobs
+            WHERE id = $1
+            RETURNING id, job_type, payload, created_at
+        )
+        INSERT INTO jobs (id, job_type, payload, created_at)
+        SELECT id, job_type, payload, created_at
+        FROM requeued
+        "#,
+    )
+    .bind(job_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Permanently delete a dead-lettered job.
+///
+/// Returns `false` if there is no dead-lettered job with the given id.
+#[tracing::instrument(name = "Purge dead-lettered job", skip(pool))]
+pub async fn purge_dead_letter_job(pool: &PgPool, job_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(r#"DELETE FROM dead_letter_jobs WHERE id = $1"#)
+        .bind(job_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::{
+        dead_letter_job, list_dead_letter_jobs, purge_dead_letter_job, requeue_dead_letter_job,
+    };
+    use crate::jobs::enqueue_job;
+
+    async fn dead_lettered_job(pool: &PgPool) -> Uuid {
+        let job_id = enqueue_job(pool, "cleanup", serde_json::json!({"n": 1}))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE jobs SET n_retries = 4 WHERE id = $1")
+            .bind(job_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        let error = anyhow::anyhow!("Connection refused").context("Failed to clean up");
+        dead_letter_job(&mut transaction, job_id, &error)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        job_id
+    }
+
+    #[sqlx::test]
+    async fn dead_lettered_jobs_keep_their_error_chain(pool: PgPool) {
+        let job_id = dead_lettered_job(&pool).await;
+
+        let jobs = list_dead_letter_jobs(&pool).await.unwrap();
+
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, job_id);
+        assert_eq!(jobs[0].n_retries, 4);
+        assert_eq!(jobs[0].payload, serde_json::json!({"n": 1}));
+        assert!(jobs[0].last_error.contains("Failed to clean up"));
+        assert!(jobs[0].last_error.contains("Connection refused"));
+    }
+
+    #[sqlx::test]
+    async fn requeued_jobs_get_a_fresh_retry_budget(pool: PgPool) {
+        let job_id = dead_lettered_job(&pool).await;
+
+        assert!(requeue_dead_letter_job(&pool, job_id).await.unwrap());
+
+        let (n_retries, payload): (i32, serde_json::Value) =
+            sqlx::query_as("SELECT n_retries, payload FROM jobs WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(n_retries, 0);
+        assert_eq!(payload, serde_json::json!({"n": 1}));
+        assert!(list_dead_letter_jobs(&pool).await.unwrap().is_empty());
+        assert!(!requeue_dead_letter_job(&pool, job_id).await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn purged_jobs_are_gone_for_good(pool: PgPool) {
+        let job_id = dead_lettered_job(&pool).await;
+
+        assert!(purge_dead_letter_job(&pool, job_id).await.unwrap());
+
+        assert!(list_dead_letter_jobs(&pool).await.unwrap().is_empty());
+        let jobs: i64 = sqlx::query_scalar("SELECT count(*) FROM jobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(jobs, 0);
+        assert!(!purge_dead_letter_job(&pool, job_id).await.unwrap());
+    }
+}
@@ -0,0 +1,15 @@
+mod cleanup_unconfirmed_subscribers;
+mod dead_letter;
+mod registry;
+mod retry;
+mod scheduler;
+mod worker;
+
+pub use cleanup_unconfirmed_subscribers::CleanupUnconfirmedSubscribers;
+pub use dead_letter::{
+    list_dead_letter_jobs, purge_dead_letter_job, requeue_dead_letter_job, DeadLetterJob,
+};
+pub use registry::*;
+pub use retry::*;
+pub use scheduler::Scheduler;
+pub use worker::*;
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::jobs::RetryPolicy;
+
+/// A kind of background job the worker knows how to execute.
+///
+/// Implement it for each job kind and register the handler with a
+/// [`JobRegistry`]; the worker dispatches on [`JobHandler::job_type`].
+#[async_trait::async_trait]
+pub trait JobHandler: Send + Sync + 'static {
+    /// Identifier stored in the `job_type` column for jobs of this kind.
+    fn job_type(&self) -> &'static str;
+
+    /// How failed jobs of this kind are retried before being dead-lettered.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    async fn handle(&self, payload: serde_json::Value) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    handlers: HashMap<&'static str, Arc<dyn JobHandler>>,
+}
+
+impl JobRegistry {
+    /// Register `handler` for its job type.
+    ///
+    /// Panics if a handler for the same job type has already been registered.
+    pub fn register(mut self, handler: impl JobHandler) -> Self {
+        let job_type = handler.job_type();
+        if self.handlers.insert(job_type, Arc::new(handler)).is_some() {
+            panic!("A handler for `{}` jobs is already registered", job_type);
+        }
+        self
+    }
+
+    pub fn get(&self, job_type: &str) -> Option<Arc<dyn JobHandler>> {
+        self.handlers.get(job_type).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JobHandler, JobRegistry};
+    use claims::assert_some;
+
+    struct NoopHandler(&'static str);
+
+    #[async_trait::async_trait]
+    impl JobHandler for NoopHandler {
+        fn job_type(&self) -> &'static str {
+            self.0
+        }
+
+        async fn handle(&self, _payload: serde_json::Value) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registered_handlers_are_looked_up_by_job_type() {
+        let registry = JobRegistry::default()
+            .register(NoopHandler("first"))
+            .register(NoopHandler("second"));
+
+        let handler = assert_some!(registry.get("second"));
+        assert_eq!(handler.job_type(), "second");
+        assert!(registry.get("third").is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn registering_the_same_job_type_twice_panics() {
+        let _ = JobRegistry::default()
+            .register(NoopHandler("first"))
+            .register(NoopHandler("first"));
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How many times a job kind is attempted and how long to wait between attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Backoff {
+    /// Wait the same amount of time before every retry.
+    Fixed(Duration),
+    /// Double the delay after every retry, starting from `base` and capped at `max`.
+    Exponential { base: Duration, max: Duration },
+    /// Like `Exponential`, but a random amount up to half of the delay is shaved off
+    /// so that jobs failing together do not retry in lockstep.
+    ExponentialWithJitter { base: Duration, max: Duration },
+}
+
+impl RetryPolicy {
+    /// Fail straight to the dead-letter table on the first error.
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Backoff::Fixed(Duration::ZERO),
+        }
+    }
+
+    /// Whether a job that has already been retried `n_retries` times and just failed
+    /// again should be attempted once more.
+    pub fn should_retry(&self, n_retries: u32) -> bool {
+        n_retries.saturating_add(1) < self.max_attempts
+    }
+
+    /// Delay before the next attempt of a job that has been retried `n_retries` times.
+    pub fn delay(&self, n_retries: u32) -> Duration {
+        match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { base, max } => exponential(base, max, n_retries),
+            Backoff::ExponentialWithJitter { base, max } => {
+                let delay = exponential(base, max, n_retries);
+                let jitter = rand::rng().random_range(Duration::ZERO..=delay / 2);
+                delay - jitter
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff: Backoff::ExponentialWithJitter {
+                base: Duration::from_secs(1),
+                max: Duration::from_secs(60 * 60),
+            },
+        }
+    }
+}
+
+fn exponential(base: Duration, max: Duration, n_retries: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(n_retries)).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, RetryPolicy};
+    use std::time::Duration;
+
+    fn policy(backoff: Backoff) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff,
+        }
+    }
+
+    #[test]
+    fn jobs_are_retried_until_max_attempts_is_reached() {
+        let policy = policy(Backoff::Fixed(Duration::ZERO));
+        assert!(policy.should_retry(0));
+        assert!(policy.should_retry(1));
+        assert!(!policy.should_retry(2));
+    }
+
+    #[test]
+    fn no_retry_gives_up_after_the_first_failure() {
+        assert!(!RetryPolicy::no_retry().should_retry(0));
+    }
+
+    #[test]
+    fn fixed_backoff_always_waits_the_same_amount_of_time() {
+        let policy = policy(Backoff::Fixed(Duration::from_secs(5)));
+        assert_eq!(policy.delay(0), Duration::from_secs(5));
+        assert_eq!(policy.delay(10), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn exponential_backoff_doubles_up_to_the_cap() {
+        let policy = policy(Backoff::Exponential {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+        });
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(4), Duration::from_secs(10));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jittered_backoff_stays_between_half_and_the_full_exponential_delay() {
+        let policy = policy(Backoff::ExponentialWithJitter {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        });
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(8));
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{CatchUpPolicy, ScheduledJobSettings, Settings},
+    jobs::enqueue_job,
+    startup::{get_connection_pool, get_redis_connection},
+};
+
+/// How often the scheduler checks whether a tick is due.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Upper bound on how long a replica holds a job's lock if it dies mid-tick.
+const LOCK_TTL: Duration = Duration::from_secs(30);
+/// A tick this old is considered missed rather than simply due.
+const MISSED_TICK_GRACE: chrono::TimeDelta = chrono::TimeDelta::seconds(60);
+/// Caps the number of jobs enqueued in one pass when running every missed tick.
+const MAX_TICKS_PER_PASS: usize = 100;
+
+/// Releases the lock only if it is still held by this replica.
+const RELEASE_LOCK_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+else
+    return 0
+end
+"#;
+
+struct ScheduledJob {
+    settings: ScheduledJobSettings,
+    schedule: cron::Schedule,
+}
+
+/// Enqueues the jobs configured under `scheduler.jobs` when their cron expression fires.
+///
+/// Every replica runs a scheduler; a Redis lock per job makes sure only one of them
+/// handles a given tick. The last handled tick is stored in Postgres so that ticks
+/// missed during downtime can be caught up according to the job's [`CatchUpPolicy`].
+pub struct Scheduler {
+    pool: PgPool,
+    redis: ConnectionManager,
+    jobs: Vec<ScheduledJob>,
+    instance_id: String,
+}
+
+impl Scheduler {
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        let pool = get_connection_pool(&configuration.database);
+        let redis = get_redis_connection(&configuration.redis_url).await?;
+        let jobs = configuration
+            .scheduler
+            .jobs
+            .into_iter()
+            .map(|settings| {
+                let schedule = settings.schedule().with_context(|| {
+                    format!(
+                        "Invalid cron expression for scheduled job `{}`",
+                        settings.name
+                    )
+                })?;
+                Ok(ScheduledJob { settings, schedule })
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+        Ok(Self {
+            pool,
+            redis,
+            jobs,
+            instance_id: Uuid::new_v4().to_string(),
+        })
+    }
+
+    pub async fn run_until_stopped(
+        mut self,
+        shutdown: CancellationToken,
+    ) -> Result<(), anyhow::Error> {
+        while !shutdown.is_cancelled() {
+            for job in &self.jobs {
+                // Errors are logged by `try_schedule_job`; the next poll tries again.
+                let _ = try_schedule_job(&self.pool, &mut self.redis, job, &self.instance_id).await;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                _ = shutdown.cancelled() => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(
+    skip(pool, redis, job, instance_id),
+    fields(scheduled_job = %job.settings.name),
+    err
+)]
+async fn try_schedule_job(
+    pool: &PgPool,
+    redis: &mut ConnectionManager,
+    job: &ScheduledJob,
+    instance_id: &str,
+) -> Result<(), anyhow::Error> {
+    let lock_key = format!("scheduler:lock:{}", job.settings.name);
+    let acquired: Option<String> = redis::cmd("SET")
+        .arg(&lock_key)
+        .arg(instance_id)
+        .arg("NX")
+        .arg("PX")
+        .arg(LOCK_TTL.as_millis() as u64)
+        .query_async(redis)
+        .await
+        .context("Failed to acquire the scheduler lock.")?;
+    if acquired.is_none() {
+        return Ok(());
+    }
+
+    let outcome = enqueue_due_ticks(pool, job).await;
+    redis::Script::new(RELEASE_LOCK_SCRIPT)
+        .key(&lock_key)
+        .arg(instance_id)
+        .invoke_async::<i64>(redis)
+        .await
+        .context("Failed to release the scheduler lock.")?;
+    outcome
+}
+
+async fn enqueue_due_ticks(pool: &PgPool, job: &ScheduledJob) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let now = Utc::now();
+    let last_tick: Option<DateTime<Utc>> = sqlx::query_scalar(
+        r#"SELECT last_tick_at FROM scheduled_job_ticks WHERE name = $1 FOR UPDATE"#,
+    )
+    .bind(&job.settings.name)
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let Some(last_tick) = last_tick else {
+        // First time we see this job: start counting ticks from now on.
+        sqlx::query(
+            r#"
+            INSERT INTO scheduled_job_ticks (name, last_tick_at)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(&job.settings.name)
+        .bind(now)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        return Ok(());
+    };
+
+    let Some(plan) = plan_ticks(&job.schedule, last_tick, now, job.settings.catch_up) else {
+        return Ok(());
+    };
+    for tick in &plan.enqueue {
+        enqueue_job(
+            &mut *transaction,
+            &job.settings.job_type,
+            job.settings.payload.clone(),
+        )
+        .await?;
+        tracing::info!(tick = %tick, "Enqueued scheduled job");
+    }
+    if plan.enqueue.is_empty() {
+        tracing::info!(last_tick = %plan.last_tick, "Skipped missed ticks");
+    }
+    sqlx::query(r#"UPDATE scheduled_job_ticks SET last_tick_at = $2 WHERE name = $1"#)
+        .bind(&job.settings.name)
+        .bind(plan.last_tick)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+struct TickPlan {
+    /// The ticks a job should be enqueued for.
+    enqueue: Vec<DateTime<Utc>>,
+    /// The tick to remember as handled.
+    last_tick: DateTime<Utc>,
+}
+
+/// Work out which ticks after `last_tick` are due at `now`, and which of them
+/// should be enqueued given the catch-up policy.
+///
+/// Returns `None` when no tick is due.
+fn plan_ticks(
+    schedule: &cron::Schedule,
+    last_tick: DateTime<Utc>,
+    now: DateTime<Utc>,
+    catch_up: CatchUpPolicy,
+) -> Option<TickPlan> {
+    let due = schedule.after(&last_tick).take_while(|tick| *tick <= now);
+    match catch_up {
+        CatchUpPolicy::RunAll => {
+            let enqueue: Vec<_> = due.take(MAX_TICKS_PER_PASS).collect();
+            let last_tick = *enqueue.last()?;
+            Some(TickPlan { enqueue, last_tick })
+        }
+        CatchUpPolicy::RunOnce => {
+            let last_tick = due.last()?;
+            Some(TickPlan {
+                enqueue: vec![last_tick],
+                last_tick,
+            })
+        }
+        CatchUpPolicy::Skip => {
+            let last_tick = due.last()?;
+            let enqueue = if now - last_tick <= MISSED_TICK_GRACE {
+                vec![last_tick]
+            } else {
+                vec![]
+            };
+            Some(TickPlan { enqueue, last_tick })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan_ticks, TickPlan};
+    use crate::configuration::CatchUpPolicy;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    /// Fires at the top of every hour.
+    fn hourly() -> cron::Schedule {
+        "0 0 * * * *".parse().unwrap()
+    }
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 19, hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn nothing_is_planned_before_the_next_tick() {
+        for policy in [
+            CatchUpPolicy::Skip,
+            CatchUpPolicy::RunOnce,
+            CatchUpPolicy::RunAll,
+        ] {
+            assert_eq!(
+                plan_ticks(&hourly(), at(1, 0, 0), at(1, 59, 59), policy),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn a_tick_that_just_became_due_is_enqueued_whatever_the_policy() {
+        for policy in [
+            CatchUpPolicy::Skip,
+            CatchUpPolicy::RunOnce,
+            CatchUpPolicy::RunAll,
+        ] {
+            assert_eq!(
+                plan_ticks(&hourly(), at(1, 0, 0), at(2, 0, 1), policy),
+                Some(TickPlan {
+                    enqueue: vec![at(2, 0, 0)],
+                    last_tick: at(2, 0, 0),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn skip_drops_missed_ticks() {
+        assert_eq!(
+            plan_ticks(&hourly(), at(1, 0, 0), at(4, 30, 0), CatchUpPolicy::Skip),
+            Some(TickPlan {
+                enqueue: vec![],
+                last_tick: at(4, 0, 0),
+            })
+        );
+    }
+
+    #[test]
+    fn run_once_enqueues_a_single_job_for_all_missed_ticks() {
+        assert_eq!(
+            plan_ticks(&hourly(), at(1, 0, 0), at(4, 30, 0), CatchUpPolicy::RunOnce),
+            Some(TickPlan {
+                enqueue: vec![at(4, 0, 0)],
+                last_tick: at(4, 0, 0),
+            })
+        );
+    }
+
+    #[test]
+    fn run_all_enqueues_a_job_for_every_missed_tick() {
+        assert_eq!(
+            plan_ticks(&hourly(), at(1, 0, 0), at(4, 30, 0), CatchUpPolicy::RunAll),
+            Some(TickPlan {
+                enqueue: vec![at(2, 0, 0), at(3, 0, 0), at(4, 0, 0)],
+                last_tick: at(4, 0, 0),
+            })
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, Row, Transaction};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    jobs::{dead_letter::dead_letter_job, JobRegistry, RetryPolicy},
+    startup::get_connection_pool,
+};
+
+/// How long the worker sleeps when the queue is empty.
+const EMPTY_QUEUE_BACKOFF: Duration = Duration::from_secs(10);
+/// How long the worker sleeps after failing to talk to the database.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub struct JobWorker {
+    pool: PgPool,
+    registry: JobRegistry,
+}
+
+impl JobWorker {
+    pub fn build(configuration: Settings, registry: JobRegistry) -> Self {
+        let pool = get_connection_pool(&configuration.database);
+        Self { pool, registry }
+    }
+
+    /// Process jobs until `shutdown` is cancelled.
+    ///
+    /// A job that is running when shutdown starts is allowed to finish.
+    pub async fn run_until_stopped(self, shutdown: CancellationToken) -> Result<(), anyhow::Error> {
+        worker_loop(self.pool, self.registry, shutdown).await
+    }
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    registry: JobRegistry,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        let backoff = match try_execute_job(&pool, &registry).await {
+            Ok(ExecutionOutcome::EmptyQueue) => EMPTY_QUEUE_BACKOFF,
+            Err(_) => ERROR_BACKOFF,
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+    Ok(())
+}
+
+/// Add a job to the queue.
+///
+/// Accepts any executor so that callers can enqueue within their own transaction.
+#[tracing::instrument(name = "Enqueue background job", skip(executor, payload))]
+pub async fn enqueue_job<'e, E>(
+    executor: E,
+    job_type: &str,
+    payload: serde_json::Value,
+) -> Result<Uuid, sqlx::Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let job_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO jobs (id, job_type, payload)
+        VALUES ($1, $2, $3)
+        "#,
+    )
+    .bind(job_id)
+    .bind(job_type)
+    .bind(payload)
+    .execute(executor)
+    .await?;
+    Ok(job_id)
+}
+
+/// Claim the oldest job that is due and run its handler.
+///
+/// The row stays locked for the duration of the handler, so concurrent workers
+/// (in this process or in other replicas) skip it rather than running it twice.
+/// A failed job is either rescheduled according to its handler's [`RetryPolicy`]
+/// or, once it has used up its attempts, moved to the dead-letter table.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        job_id = tracing::field::Empty,
+        job_type = tracing::field::Empty,
+        n_retries = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_job(
+    pool: &PgPool,
+    registry: &JobRegistry,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let job = sqlx::query(
+        r#"
+        SELECT id, job_type, payload, n_retries
+        FROM jobs
+        WHERE execute_after <= now()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(job) = job else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    let job_id: Uuid = job.try_get("id")?;
+    let job_type: String = job.try_get("job_type")?;
+    let payload: serde_json::Value = job.try_get("payload")?;
+    let n_retries: i32 = job.try_get("n_retries")?;
+    tracing::Span::current()
+        .record("job_id", tracing::field::display(job_id))
+        .record("job_type", tracing::field::display(&job_type))
+        .record("n_retries", n_retries);
+
+    let outcome = match registry.get(&job_type) {
+        Some(handler) => handler
+            .handle(payload)
+            .await
+            .map_err(|e| (e, handler.retry_policy())),
+        None => Err((
+            anyhow!("No handler is registered for `{}` jobs.", job_type),
+            RetryPolicy::no_retry(),
+        )),
+    };
+
+    match outcome {
+        Ok(()) => delete_job(&mut transaction, job_id).await?,
+        Err((e, retry_policy)) => {
+            let n_retries = n_retries.try_into().unwrap_or(u32::MAX);
+            if retry_policy.should_retry(n_retries) {
+                let delay = retry_policy.delay(n_retries);
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    retry_in_milliseconds = delay.as_millis() as u64,
+                    "Failed to execute background job. Retrying later.",
+                );
+                schedule_retry(&mut transaction, job_id, delay).await?;
+            } else {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to execute background job. Moving it to the dead-letter table.",
+                );
+                dead_letter_job(&mut transaction, job_id, &e).await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn delete_job(
+    transaction: &mut Transaction<'_, Postgres>,
+    job_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"DELETE FROM jobs WHERE id = $1"#)
+        .bind(job_id)
+        .execute(&mut **transaction)
+        .await?;
+    Ok(())
+}
+
+async fn schedule_retry(
+    transaction: &mut Transaction<'_, Postgres>,
+    job_id: Uuid,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query(
+        r#"
+        UPDATE jobs
+        SET n_retries = n_retries + 1,
+            execute_after = $2
+        WHERE id = $1
+        "#,
+    )
+    .bind(job_id)
+    .bind(execute_after)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use sqlx::{PgPool, Row};
+    use uuid::Uuid;
+
+    use super::{enqueue_job, try_execute_job, ExecutionOutcome};
+    use crate::jobs::{Backoff, JobHandler, JobRegistry, RetryPolicy};
+
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        backoff: Backoff::Exponential {
+            base: Duration::from_secs(60),
+            max: Duration::from_secs(60 * 60),
+        },
+    };
+
+    /// Records the payloads it is handed, and fails if told to.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        payloads: Arc<Mutex<Vec<serde_json::Value>>>,
+        fail: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl JobHandler for Recorder {
+        fn job_type(&self) -> &'static str {
+            "record"
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            RETRY_POLICY
+        }
+
+        async fn handle(&self, payload: serde_json::Value) -> Result<(), anyhow::Error> {
+            self.payloads.lock().unwrap().push(payload);
+            if self.fail {
+                anyhow::bail!("The recorder was told to fail");
+            }
+            Ok(())
+        }
+    }
+
+    async fn execute(pool: &PgPool, registry: &JobRegistry) -> ExecutionOutcome {
+        try_execute_job(pool, registry).await.unwrap()
+    }
+
+    async fn set_retries(pool: &PgPool, job_id: Uuid, n_retries: i32) {
+        sqlx::query("UPDATE jobs SET n_retries = $2 WHERE id = $1")
+            .bind(job_id)
+            .bind(n_retries)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn succeeded_jobs_are_removed_from_the_queue(pool: PgPool) {
+        let recorder = Recorder::default();
+        let registry = JobRegistry::default().register(recorder.clone());
+        enqueue_job(&pool, "record", serde_json::json!({"n": 1}))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            execute(&pool, &registry).await,
+            ExecutionOutcome::TaskCompleted
+        ));
+        assert!(matches!(
+            execute(&pool, &registry).await,
+            ExecutionOutcome::EmptyQueue
+        ));
+        assert_eq!(
+            *recorder.payloads.lock().unwrap(),
+            [serde_json::json!({"n": 1})]
+        );
+    }
+
+    #[sqlx::test]
+    async fn jobs_claimed_by_another_worker_are_skipped(pool: PgPool) {
+        let recorder = Recorder::default();
+        let registry = JobRegistry::default().register(recorder.clone());
+        let claimed = enqueue_job(&pool, "record", serde_json::json!({"n": 1}))
+            .await
+            .unwrap();
+        enqueue_job(&pool, "record", serde_json::json!({"n": 2}))
+            .await
+            .unwrap();
+
+        // Another worker, holding the first job's row.
+        let mut other_worker = pool.begin().await.unwrap();
+        sqlx::query("SELECT id FROM jobs WHERE id = $1 FOR UPDATE")
+            .bind(claimed)
+            .execute(&mut *other_worker)
+            .await
+            .unwrap();
+
+        execute(&pool, &registry).await;
+        assert!(matches!(
+            execute(&pool, &registry).await,
+            ExecutionOutcome::EmptyQueue
+        ));
+        assert_eq!(
+            *recorder.payloads.lock().unwrap(),
+            [serde_json::json!({"n": 2})]
+        );
+
+        other_worker.rollback().await.unwrap();
+        execute(&pool, &registry).await;
+        assert_eq!(recorder.payloads.lock().unwrap().len(), 2);
+    }
+
+    #[sqlx::test]
+    async fn failed_jobs_are_retried_with_backoff(pool: PgPool) {
+        let registry = JobRegistry::default().register(Recorder {
+            fail: true,
+            ..Recorder::default()
+        });
+        let job_id = enqueue_job(&pool, "record", serde_json::json!({}))
+            .await
+            .unwrap();
+        set_retries(&pool, job_id, 1).await;
+
+        execute(&pool, &registry).await;
+
+        let job = sqlx::query("SELECT n_retries, execute_after FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(job.get::<i32, _>("n_retries"), 2);
+        // 60s, doubled once.
+        let delay = job.get::<chrono::DateTime<Utc>, _>("execute_after") - Utc::now();
+        assert!(
+            delay > chrono::Duration::seconds(110) && delay <= chrono::Duration::seconds(120),
+            "{}",
+            delay
+        );
+        // Not due yet.
+        assert!(matches!(
+            execute(&pool, &registry).await,
+            ExecutionOutcome::EmptyQueue
+        ));
+    }
+
+    #[sqlx::test]
+    async fn jobs_out_of_attempts_are_dead_lettered(pool: PgPool) {
+        let registry = JobRegistry::default().register(Recorder {
+            fail: true,
+            ..Recorder::default()
+        });
+        let job_id = enqueue_job(&pool, "record", serde_json::json!({"n": 1}))
+            .await
+            .unwrap();
+        set_retries(&pool, job_id, RETRY_POLICY.max_attempts as i32 - 1).await;
+
+        execute(&pool, &registry).await;
+
+        let jobs: i64 = sqlx::query_scalar("SELECT count(*) FROM jobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(jobs, 0);
+        let dead_letter =
+            sqlx::query("SELECT payload, last_error FROM dead_letter_jobs WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            dead_letter.get::<serde_json::Value, _>("payload"),
+            serde_json::json!({"n": 1})
+        );
+        assert!(dead_letter
+            .get::<String, _>("last_error")
+            .contains("The recorder was told to fail"));
+    }
+
+    #[sqlx::test]
+    async fn jobs_without_a_handler_are_dead_lettered_straight_away(pool: PgPool) {
+        let job_id = enqueue_job(&pool, "unknown", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        execute(&pool, &JobRegistry::default()).await;
+
+        let last_error: String =
+            sqlx::query_scalar("SELECT last_error FROM dead_letter_jobs WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(last_error.contains("No handler is registered for `unknown` jobs."));
+    }
+}
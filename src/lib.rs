@@ -1,4 +1,16 @@
+pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod jobs;
+pub mod markdown;
+pub mod reload;
+pub mod routes;
+pub mod shutdown;
+pub mod signing;
 pub mod startup;
+pub mod suppression;
+pub mod utils;
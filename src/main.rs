@@ -1,42 +1,206 @@
-use mega_task_runner::{configuration::get_configuration, startup::Application};
-use std::fmt::{Debug, Display};
-use tokio::task::JoinError;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use mega_task_runner::{
+    authentication::create_user,
+    configuration::{
+        get_configuration, load_configuration, parse_override, print_configuration,
+        ConfigurationError, ConfigurationOptions, Environment, Settings,
+    },
+    email_client::SharedEmailClient,
+    issue_delivery_worker::IssueDeliveryWorker,
+    jobs::{CleanupUnconfirmedSubscribers, JobRegistry, JobWorker, Scheduler},
+    reload::ConfigurationReloader,
+    shutdown::{shutdown_signal, supervise},
+    startup::{get_connection_pool, get_redis_connection, Application, MIGRATOR},
+};
+use std::path::PathBuf;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
-
-    let configuration = get_configuration().expect("Failed to read configuration");
-    let application = Application::build(configuration.clone()).await?;
-    let application_task = tokio::spawn(application.run_until_stopped());
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Directory holding the YAML configuration files [default: ./configuration]
+    #[arg(long, global = true, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+    /// Which `{environment}.yaml` to read [default: $APP_ENVIRONMENT, or local]
+    #[arg(long, global = true, value_parser = parse_environment)]
+    environment: Option<Environment>,
+    /// Override a setting, e.g. `--set application.port=8080`. Can be repeated.
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+}
 
-    tokio::select! {
-        o = application_task => report_exit("API", o)
+impl Cli {
+    fn configuration_options(&self) -> ConfigurationOptions {
+        ConfigurationOptions {
+            directory: self.config_dir.clone(),
+            environment: self.environment,
+            overrides: self.overrides.clone(),
+        }
     }
+}
 
-    Ok(())
+fn parse_environment(environment: &str) -> Result<Environment, String> {
+    environment.to_string().try_into()
 }
 
-fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
-    match outcome {
-        Ok(Ok(())) => {
-            tracing::info!("{} has exited", task_name)
+#[derive(Subcommand)]
+enum Command {
+    /// Apply pending database migrations and exit.
+    Migrate,
+    /// Create an admin user. The password is read from standard input.
+    CreateAdmin { username: String },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Validate the configuration, exiting with a non-zero status if it is invalid.
+    Check,
+    /// Print the effective configuration, secrets redacted, with the source of every value.
+    /// An invalid configuration is printed as merged, followed by its problems.
+    Print,
+}
+
+/// Report why the configuration is invalid and exit with a non-zero status.
+fn exit_with(e: ConfigurationError) -> ! {
+    eprintln!("{}", format!("{:?}", e).trim_end());
+    std::process::exit(1);
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let (log_filter, log_filter_handle) =
+        tracing_subscriber::reload::Layer::new(EnvFilter::from_default_env());
+    tracing_subscriber::registry()
+        .with(log_filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let cli = Cli::parse();
+    let options = cli.configuration_options();
+    if let Some(Command::Config { command }) = cli.command {
+        match command {
+            ConfigCommand::Check => match get_configuration(&options) {
+                Ok(_) => println!("The configuration is valid."),
+                Err(e) => exit_with(e),
+            },
+            ConfigCommand::Print => match print_configuration(&options) {
+                Ok(printed) => {
+                    println!("{}", printed.yaml.trim_end());
+                    if let Some(e) = printed.error {
+                        exit_with(e);
+                    }
+                }
+                Err(e) => exit_with(e),
+            },
         }
-        Ok(Err(e)) => {
-            tracing::error!(
-                error.cause_chain = ?e,
-                error.message = %e,
-                "{} failed",
-                task_name
-            )
+        return Ok(());
+    }
+    let loaded_configuration =
+        load_configuration(&options).context("Failed to read configuration")?;
+    let configuration = loaded_configuration.settings.clone();
+
+    match cli.command {
+        Some(Command::Migrate) => {
+            let connection_pool = get_connection_pool(&configuration.database);
+            MIGRATOR.run(&connection_pool).await?;
+            tracing::info!("Database migrations applied");
+            return Ok(());
         }
-        Err(e) => {
-            tracing::error!(
-                error.cause_chain = ?e,
-                error.message = %e,
-                "{}' task failed to complete",
-                task_name
-            )
+        Some(Command::CreateAdmin { username }) => {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']).to_string();
+            anyhow::ensure!(!password.is_empty(), "The password cannot be empty");
+            let connection_pool = get_connection_pool(&configuration.database);
+            let user_id = create_user(&username, password.into(), &connection_pool).await?;
+            tracing::info!(%user_id, "Admin user created");
+            return Ok(());
         }
+        Some(Command::Config { .. }) => unreachable!("Handled before reading the configuration"),
+        None => {}
     }
+
+    let shutdown = CancellationToken::new();
+    let shutdown_timeout = configuration.application.shutdown_timeout();
+
+    log_filter_handle.reload(configuration.application.log_filter()?)?;
+    let redis = get_redis_connection(&configuration.redis_url).await?;
+    let email_client = SharedEmailClient::new(
+        configuration
+            .email_client
+            .clone()
+            .client(Some(redis.clone()))
+            .context("Failed to set up the email client")?,
+    );
+    let reloader = ConfigurationReloader::new(
+        options,
+        loaded_configuration,
+        email_client.clone(),
+        log_filter_handle,
+        Some(redis),
+    )?;
+    let reloader_task = tokio::spawn(reloader.run_until_stopped(shutdown.clone()));
+
+    let application = Application::build(configuration.clone(), email_client.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped(shutdown.clone()));
+    let worker = JobWorker::build(configuration.clone(), job_registry(&configuration));
+    let worker_task = tokio::spawn(worker.run_until_stopped(shutdown.clone()));
+    let delivery_worker = IssueDeliveryWorker::build(configuration.clone(), email_client);
+    let delivery_worker_task = tokio::spawn(delivery_worker.run_until_stopped(shutdown.clone()));
+    let scheduler = Scheduler::build(configuration.clone()).await?;
+    let scheduler_task = tokio::spawn(scheduler.run_until_stopped(shutdown.clone()));
+
+    let signal_listener = {
+        let shutdown = shutdown.clone();
+        async move {
+            tokio::select! {
+                _ = shutdown_signal() => {
+                    tracing::info!("Shutdown signal received, draining in-flight work");
+                    shutdown.cancel();
+                }
+                _ = shutdown.cancelled() => {}
+            }
+        }
+    };
+
+    tokio::join!(
+        signal_listener,
+        supervise("API", application_task, &shutdown, shutdown_timeout),
+        supervise(
+            "Background worker",
+            worker_task,
+            &shutdown,
+            shutdown_timeout
+        ),
+        supervise(
+            "Newsletter delivery worker",
+            delivery_worker_task,
+            &shutdown,
+            shutdown_timeout
+        ),
+        supervise("Scheduler", scheduler_task, &shutdown, shutdown_timeout),
+        supervise(
+            "Configuration reloader",
+            reloader_task,
+            &shutdown,
+            shutdown_timeout
+        ),
+    );
+
+    Ok(())
+}
+
+/// Background job kinds this binary knows how to execute.
+fn job_registry(configuration: &Settings) -> JobRegistry {
+    let connection_pool = get_connection_pool(&configuration.database);
+    JobRegistry::default().register(CleanupUnconfirmedSubscribers::new(connection_pool))
 }
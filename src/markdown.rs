@@ -0,0 +1,155 @@
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use syntect::{highlighting::Theme, html::highlighted_html_for_string, parsing::SyntaxSet};
+
+/// Line width of the plain-text rendering.
+const TEXT_WIDTH: usize = 78;
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    syntect::highlighting::ThemeSet::load_defaults()
+        .themes
+        .remove("InspiredGitHub")
+        .expect("syntect ships with the InspiredGitHub theme")
+});
+
+#[derive(Debug)]
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+/// Render Markdown to sanitized HTML and to plain text, ready to be emailed.
+///
+/// Fenced code is highlighted with inline styles, since most email clients drop
+/// `<style>` elements. Every `http(s)` link goes through `rewrite_link`, which returns
+/// the URL to use in its place.
+pub fn render_markdown(
+    markdown: &str,
+    mut rewrite_link: impl FnMut(&str) -> String,
+) -> Result<RenderedMarkdown, anyhow::Error> {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut events = Vec::new();
+    let mut code_block: Option<(String, String)> = None;
+    for event in Parser::new_ext(markdown, options) {
+        match (event, code_block.as_mut()) {
+            (Event::Start(Tag::CodeBlock(kind)), _) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().map(Into::into),
+                    CodeBlockKind::Indented => None,
+                };
+                code_block = Some((language.unwrap_or_default(), String::new()));
+            }
+            (Event::Text(text), Some((_, code))) => code.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some((language, code))) => {
+                let html = highlight(code, language)?;
+                events.push(Event::Html(html.into()));
+                code_block = None;
+            }
+            (
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }),
+                _,
+            ) => {
+                let dest_url = if is_http(&dest_url) {
+                    CowStr::from(rewrite_link(&dest_url))
+                } else {
+                    dest_url
+                };
+                events.push(Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }));
+            }
+            (event, _) => events.push(event),
+        }
+    }
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    let html = ammonia::Builder::default()
+        .add_tag_attributes("pre", &["style"])
+        .add_tag_attributes("span", &["style"])
+        .clean(&html)
+        .to_string();
+    let text = html2text::from_read(html.as_bytes(), TEXT_WIDTH)
+        .context("Failed to render the plain-text version.")?;
+    Ok(RenderedMarkdown { html, text })
+}
+
+fn highlight(code: &str, language: &str) -> Result<String, anyhow::Error> {
+    let syntax = SYNTAXES
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    highlighted_html_for_string(code, &SYNTAXES, syntax, &THEME)
+        .context("Failed to highlight a code block.")
+}
+
+fn is_http(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_markdown;
+
+    fn render(markdown: &str) -> super::RenderedMarkdown {
+        render_markdown(markdown, |url| url.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html_and_text() {
+        let rendered = render("# Hello\n\nSome *emphasis*.");
+
+        assert!(rendered.html.contains("<h1>Hello</h1>"));
+        assert!(rendered.html.contains("<em>emphasis</em>"));
+        assert!(rendered.text.contains("Hello"));
+        assert!(!rendered.text.contains('<'));
+    }
+
+    #[test]
+    fn raw_html_is_sanitized() {
+        let rendered = render("Hi <script>alert(1)</script> [x](javascript:alert(1))");
+
+        assert!(!rendered.html.contains("<script"));
+        assert!(!rendered.html.contains("javascript:"));
+    }
+
+    #[test]
+    fn fenced_code_is_highlighted_with_inline_styles() {
+        let rendered = render("```rust\nfn main() {}\n```");
+
+        assert!(rendered.html.contains("<pre style="));
+        assert!(rendered.html.contains("<span style="));
+        assert!(rendered.html.contains("main"));
+    }
+
+    #[test]
+    fn http_links_are_rewritten() {
+        let mut seen = Vec::new();
+        let rendered = render_markdown(
+            "[site](https://example.com) and [mail](mailto:a@example.com)",
+            |url| {
+                seen.push(url.to_owned());
+                "https://tracker.example.com/1".into()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(seen, vec!["https://example.com"]);
+        assert!(rendered
+            .html
+            .contains("href=\"https://tracker.example.com/1\""));
+        assert!(rendered.html.contains("href=\"mailto:a@example.com\""));
+        assert!(rendered.text.contains("https://tracker.example.com/1"));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use redis::aio::ConnectionManager;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::{
+    configuration::{
+        load_configuration, ConfigurationError, ConfigurationOptions, LoadedConfiguration,
+    },
+    email_client::SharedEmailClient,
+};
+
+/// How often the configuration files are checked for changes.
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Swaps the `tracing` filter of the running process.
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Reloads the configuration on SIGHUP, or when one of its YAML files changes.
+///
+/// Only the email client and the log filter change at runtime. A new configuration that
+/// changes anything else, e.g. the port or the database host, needs a restart: it is
+/// rejected as a whole and the current one stays in use. So is one whose email client
+/// cannot be built, e.g. because its rate limit is infinite.
+pub struct ConfigurationReloader {
+    options: ConfigurationOptions,
+    current: LoadedConfiguration,
+    email_client: SharedEmailClient,
+    log_filter: LogFilterHandle,
+    /// Shares the rate limit with the other replicas, as in
+    /// [`EmailClientSettings::client`](crate::configuration::EmailClientSettings::client).
+    redis: Option<ConnectionManager>,
+    directory: PathBuf,
+}
+
+impl ConfigurationReloader {
+    /// `current` must have been loaded with `options`.
+    pub fn new(
+        options: ConfigurationOptions,
+        current: LoadedConfiguration,
+        email_client: SharedEmailClient,
+        log_filter: LogFilterHandle,
+        redis: Option<ConnectionManager>,
+    ) -> Result<Self, ConfigurationError> {
+        Ok(Self {
+            directory: options.directory()?,
+            options,
+            current,
+            email_client,
+            log_filter,
+            redis,
+        })
+    }
+
+    pub async fn run_until_stopped(
+        mut self,
+        shutdown: CancellationToken,
+    ) -> Result<(), std::io::Error> {
+        #[cfg(unix)]
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        let mut poll = tokio::time::interval(FILE_POLL_INTERVAL);
+        let mut modified = modification_times(&self.directory);
+        loop {
+            #[cfg(unix)]
+            let hangup = hangup.recv();
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = hangup => {
+                    tracing::info!("SIGHUP received, reloading the configuration");
+                }
+                _ = poll.tick() => {
+                    let now = modification_times(&self.directory);
+                    if now == modified {
+                        continue;
+                    }
+                    modified = now;
+                    tracing::info!("Configuration files changed, reloading the configuration");
+                }
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+            self.reload();
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn reload(&mut self) {
+        let configuration = match load_configuration(&self.options) {
+            Ok(configuration) => configuration,
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Rejected the new configuration, the current one stays in use"
+                );
+                return;
+            }
+        };
+        let changed_keys = self.current.changed_keys(&configuration);
+        let restart_required: Vec<_> = changed_keys
+            .iter()
+            .filter(|key| !is_reloadable(key))
+            .collect();
+        if !restart_required.is_empty() {
+            tracing::warn!(
+                keys = ?restart_required,
+                "Rejected the new configuration: these keys only change with a restart"
+            );
+            return;
+        }
+
+        if changed_keys
+            .iter()
+            .any(|key| key.starts_with("email_client."))
+        {
+            let email_settings = configuration.settings.email_client.clone();
+            let mut email_client = match email_settings.client(self.redis.clone()) {
+                Ok(email_client) => email_client,
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Rejected the new configuration, the current one stays in use"
+                    );
+                    return;
+                }
+            };
+            if !changed_keys
+                .iter()
+                .any(|key| key.starts_with("email_client.circuit_breaker."))
+            {
+                email_client = email_client.with_circuit_breaker_of(&self.email_client.load());
+            }
+            self.email_client.store(email_client);
+        }
+        if changed_keys
+            .iter()
+            .any(|key| key == "application.log_filter")
+        {
+            // Validated along with the rest of the configuration.
+            if let Ok(filter) = configuration.settings.application.log_filter() {
+                if let Err(e) = self.log_filter.reload(filter) {
+                    tracing::error!(error.message = %e, "Failed to swap the log filter");
+                }
+            }
+        }
+        tracing::info!(keys = ?changed_keys, "Configuration reloaded");
+        self.current = configuration;
+    }
+}
+
+/// Whether a change to `key` takes effect without a restart.
+///
+/// `email_client.postmark_webhook` is read by the API when it starts, not through the
+/// email client.
+fn is_reloadable(key: &str) -> bool {
+    (key.starts_with("email_client.") && !key.starts_with("email_client.postmark_webhook."))
+        || key == "application.log_filter"
+}
+
+/// When each YAML file of `directory` was last modified. A file that is added or
+/// removed changes the result too.
+fn modification_times(directory: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return BTreeMap::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "yaml")
+        })
+        .filter_map(|path| {
+            let modified = path
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use tracing_subscriber::{reload, EnvFilter, Registry};
+
+    use super::{is_reloadable, ConfigurationReloader};
+    use crate::configuration::{load_configuration, ConfigurationOptions, Environment};
+    use crate::email_client::SharedEmailClient;
+
+    /// A configuration directory under the system temporary directory, holding the
+    /// shipped `base.yaml` and a `local.yaml` of our own. Removed when dropped.
+    struct ConfigurationDirectory(PathBuf);
+
+    impl ConfigurationDirectory {
+        fn new(local: &str) -> Self {
+            let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+            std::fs::create_dir(&path).unwrap();
+            std::fs::write(
+                path.join("base.yaml"),
+                include_str!("configuration/base.yaml"),
+            )
+            .unwrap();
+            let directory = Self(path);
+            directory.write_local(local);
+            directory
+        }
+
+        fn write_local(&self, local: &str) {
+            std::fs::write(self.0.join("local.yaml"), local).unwrap();
+        }
+
+        fn options(&self) -> ConfigurationOptions {
+            ConfigurationOptions {
+                directory: Some(self.0.clone()),
+                environment: Some(Environment::Local),
+                overrides: Vec::new(),
+            }
+        }
+    }
+
+    impl Drop for ConfigurationDirectory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The reloader, the layer its log filter handle points to, and the email client it
+    /// swaps.
+    fn reloader(
+        directory: &ConfigurationDirectory,
+    ) -> (
+        ConfigurationReloader,
+        reload::Layer<EnvFilter, Registry>,
+        SharedEmailClient,
+    ) {
+        let current = load_configuration(&directory.options()).unwrap();
+        let email_client =
+            SharedEmailClient::new(current.settings.email_client.clone().client(None).unwrap());
+        let (log_filter, log_filter_handle) = reload::Layer::new(EnvFilter::new("info"));
+        let reloader = ConfigurationReloader::new(
+            directory.options(),
+            current,
+            email_client.clone(),
+            log_filter_handle,
+            None,
+        )
+        .unwrap();
+        (reloader, log_filter, email_client)
+    }
+
+    #[tokio::test]
+    async fn a_new_email_client_is_swapped_in_when_its_settings_change() {
+        let directory = ConfigurationDirectory::new("");
+        let (mut reloader, _log_filter, email_client) = reloader(&directory);
+        let before = email_client.load();
+
+        directory.write_local("email_client:\n    timeout_milliseconds: 5000\n");
+        reloader.reload();
+
+        assert!(!Arc::ptr_eq(&before, &email_client.load()));
+        let settings = &reloader.current.settings.email_client;
+        assert_eq!(settings.timeout_milliseconds, 5000);
+    }
+
+    #[tokio::test]
+    async fn a_change_that_needs_a_restart_keeps_the_current_configuration() {
+        let directory = ConfigurationDirectory::new("");
+        let (mut reloader, _log_filter, email_client) = reloader(&directory);
+        let before = email_client.load();
+
+        directory.write_local(
+            "application:\n    port: 8001\nemail_client:\n    timeout_milliseconds: 5000\n",
+        );
+        reloader.reload();
+
+        assert!(Arc::ptr_eq(&before, &email_client.load()));
+        assert_eq!(reloader.current.settings.application.port, 8000);
+        let settings = &reloader.current.settings.email_client;
+        assert_eq!(settings.timeout_milliseconds, 10000);
+    }
+
+    #[tokio::test]
+    async fn an_email_client_that_cannot_be_built_keeps_the_current_one() {
+        let directory = ConfigurationDirectory::new("");
+        let (mut reloader, _log_filter, email_client) = reloader(&directory);
+        let before = email_client.load();
+
+        // Valid as far as validation goes, but the rate limiter refuses it.
+        directory.write_local(
+            r#"
+email_client:
+    rate_limit:
+        requests_per_second: .inf
+"#,
+        );
+        reloader.reload();
+
+        assert!(Arc::ptr_eq(&before, &email_client.load()));
+        let rate_limit = reloader.current.settings.email_client.rate_limit.as_ref();
+        assert_eq!(rate_limit.unwrap().requests_per_second, 10.0);
+    }
+
+    #[test]
+    fn email_settings_and_the_log_filter_are_reloadable() {
+        for key in [
+            "email_client.timeout_milliseconds",
+            "email_client.sender_email",
+            "email_client.rate_limit.requests_per_second",
+            "application.log_filter",
+        ] {
+            assert!(is_reloadable(key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn structural_settings_need_a_restart() {
+        for key in [
+            "application.port",
+            "database.host",
+            "redis_url",
+            "email_client.postmark_webhook.secret",
+            "scheduler.jobs[0].schedule",
+        ] {
+            assert!(!is_reloadable(key), "{}", key);
+        }
+    }
+}
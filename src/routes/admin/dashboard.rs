@@ -0,0 +1,62 @@
+use anyhow::Context;
+use axum::{
+    extract::State,
+    response::{Html, Redirect, Response},
+    Extension,
+};
+use axum_extra::extract::{cookie::Cookie, SignedCookieJar};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{UserId, SESSION_COOKIE_NAME},
+    startup::AppState,
+    utils::{e500, html_escape},
+};
+
+pub async fn admin_dashboard(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+) -> Result<Html<String>, Response> {
+    let username = get_username(*user_id, &state.db_pool).await.map_err(e500)?;
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    <p>Welcome {}!</p>
+    <form name="logoutForm" action="/admin/logout" method="post">
+        <input type="submit" value="Logout">
+    </form>
+</body>
+</html>"#,
+        html_escape(&username)
+    )))
+}
+
+pub async fn log_out(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, Redirect), Response> {
+    if let Some(session_cookie) = jar.get(SESSION_COOKIE_NAME) {
+        state
+            .sessions
+            .delete(session_cookie.value())
+            .await
+            .map_err(e500)?;
+    }
+    let jar = jar.remove(Cookie::build(SESSION_COOKIE_NAME).path("/"));
+    Ok((jar, Redirect::to("/login")))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    sqlx::query_scalar(r#"SELECT username FROM users WHERE user_id = $1"#)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")
+}
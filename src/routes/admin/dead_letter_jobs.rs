@@ -0,0 +1,81 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use uuid::Uuid;
+
+use crate::{
+    jobs::{list_dead_letter_jobs, purge_dead_letter_job, requeue_dead_letter_job, DeadLetterJob},
+    routes::error_chain_fmt,
+    startup::AppState,
+};
+
+#[derive(thiserror::Error)]
+pub enum DeadLetterJobError {
+    #[error("There is no dead-lettered job with the provided id.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DeadLetterJobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for DeadLetterJobError {
+    fn into_response(self) -> Response {
+        match self {
+            DeadLetterJobError::NotFound => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            DeadLetterJobError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to manage dead-lettered jobs"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+pub async fn list_dead_letters(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DeadLetterJob>>, DeadLetterJobError> {
+    let jobs = list_dead_letter_jobs(&state.db_pool)
+        .await
+        .context("Failed to list dead-lettered jobs.")?;
+    Ok(Json(jobs))
+}
+
+pub async fn requeue_dead_letter(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+) -> Result<StatusCode, DeadLetterJobError> {
+    let requeued = requeue_dead_letter_job(&state.db_pool, job_id)
+        .await
+        .context("Failed to requeue a dead-lettered job.")?;
+    if !requeued {
+        return Err(DeadLetterJobError::NotFound);
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn purge_dead_letter(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+) -> Result<StatusCode, DeadLetterJobError> {
+    let purged = purge_dead_letter_job(&state.db_pool, job_id)
+        .await
+        .context("Failed to purge a dead-lettered job.")?;
+    if !purged {
+        return Err(DeadLetterJobError::NotFound);
+    }
+    Ok(StatusCode::OK)
+}
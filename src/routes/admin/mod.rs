@@ -0,0 +1,11 @@
+mod dashboard;
+mod dead_letter_jobs;
+mod newsletters;
+mod suppressions;
+mod templates;
+
+pub use dashboard::*;
+pub use dead_letter_jobs::*;
+pub use newsletters::*;
+pub use suppressions::*;
+pub use templates::*;
@@ -0,0 +1,239 @@
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Form,
+};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    markdown::render_markdown,
+    routes::{error_chain_fmt, issue_link},
+    startup::AppState,
+};
+
+/// Either `markdown_content`, or both `html_content` and `text_content`.
+#[derive(serde::Deserialize)]
+pub struct NewsletterFormData {
+    title: String,
+    #[serde(default)]
+    markdown_content: Option<String>,
+    #[serde(default)]
+    text_content: Option<String>,
+    #[serde(default)]
+    html_content: Option<String>,
+    /// Record opens and clicks, see [`track_open`] and [`track_click`].
+    ///
+    /// [`track_open`]: crate::routes::track_open
+    /// [`track_click`]: crate::routes::track_click
+    #[serde(default = "tracking_enabled_by_default")]
+    tracking_enabled: bool,
+    idempotency_key: String,
+}
+
+fn tracking_enabled_by_default() -> bool {
+    true
+}
+
+/// What gets stored for an issue.
+struct IssueContent {
+    markdown: Option<String>,
+    html: String,
+    text: String,
+    /// Targets of the rewritten links, by index.
+    links: Vec<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for PublishError {
+    fn into_response(self) -> Response {
+        match self {
+            PublishError::ValidationError(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            PublishError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to publish a newsletter issue"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip_all,
+    fields(user_id = %*user_id, idempotency_key = %form.idempotency_key)
+)]
+pub async fn publish_newsletter(
+    State(state): State<AppState>,
+    Extension(user_id): Extension<UserId>,
+    Form(form): Form<NewsletterFormData>,
+) -> Result<Response, PublishError> {
+    let NewsletterFormData {
+        title,
+        markdown_content,
+        text_content,
+        html_content,
+        tracking_enabled,
+        idempotency_key,
+    } = form;
+    let idempotency_key: IdempotencyKey = idempotency_key
+        .try_into()
+        .map_err(PublishError::ValidationError)?;
+    let issue_id = Uuid::new_v4();
+    let content = match (markdown_content, html_content, text_content) {
+        (Some(markdown), None, None) => {
+            let mut links = Vec::new();
+            let rendered = render_markdown(&markdown, |url| {
+                issue_link(&state.base_url, issue_id, record_link(&mut links, url))
+            })
+            .context("Failed to render the Markdown content.")?;
+            IssueContent {
+                markdown: Some(markdown),
+                html: rendered.html,
+                text: rendered.text,
+                links,
+            }
+        }
+        (None, Some(html), Some(text)) => IssueContent {
+            markdown: None,
+            html,
+            text,
+            links: vec![],
+        },
+        _ => {
+            return Err(PublishError::ValidationError(
+                "Provide either `markdown_content`, or both `html_content` and `text_content`."
+                    .into(),
+            ))
+        }
+    };
+    let mut transaction = match try_processing(&state.db_pool, &idempotency_key, *user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    insert_newsletter_issue(
+        &mut transaction,
+        issue_id,
+        &title,
+        &content,
+        tracking_enabled,
+    )
+    .await
+    .context("Failed to store newsletter issue details.")?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+
+    let response = (
+        StatusCode::OK,
+        "The newsletter issue has been accepted - emails will go out shortly!",
+    )
+        .into_response();
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .context("Failed to save the response for the idempotency key.")?;
+    Ok(response)
+}
+
+/// Index of `url` among the links of the issue, adding it if it is new.
+fn record_link(links: &mut Vec<String>, url: &str) -> i32 {
+    match links.iter().position(|link| link == url) {
+        Some(index) => index as i32,
+        None => {
+            links.push(url.to_owned());
+            links.len() as i32 - 1
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    title: &str,
+    content: &IssueContent,
+    tracking_enabled: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            markdown_content,
+            tracking_enabled,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        "#,
+    )
+    .bind(newsletter_issue_id)
+    .bind(title)
+    .bind(&content.text)
+    .bind(&content.html)
+    .bind(&content.markdown)
+    .bind(tracking_enabled)
+    .execute(&mut **transaction)
+    .await?;
+    for (index, url) in content.links.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO newsletter_issue_links (newsletter_issue_id, link_index, url)
+            VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(newsletter_issue_id)
+        .bind(index as i32)
+        .bind(url)
+        .execute(&mut **transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Queue one delivery per confirmed subscriber; the issue delivery worker sends them.
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+    )
+    .bind(newsletter_issue_id)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Form, Json,
+};
+
+use crate::{
+    routes::error_chain_fmt,
+    startup::AppState,
+    suppression::{
+        list_suppressions, parse_csv, suppress, to_csv, unsuppress, Suppression, SuppressionEntry,
+        SuppressionReason,
+    },
+};
+
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The entry is not on the suppression list.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for SuppressionError {
+    fn into_response(self) -> Response {
+        match self {
+            SuppressionError::ValidationError(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            SuppressionError::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            SuppressionError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to manage the suppression list"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionFormData {
+    /// An email address, or a domain.
+    entry: String,
+}
+
+pub async fn list_suppressed(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Suppression>>, SuppressionError> {
+    let suppressions = list_suppressions(&state.db_pool)
+        .await
+        .context("Failed to list suppressions.")?;
+    Ok(Json(suppressions))
+}
+
+#[tracing::instrument(name = "Add a suppression", skip(state, form), fields(entry = %form.entry))]
+pub async fn add_suppression(
+    State(state): State<AppState>,
+    Form(form): Form<SuppressionFormData>,
+) -> Result<StatusCode, SuppressionError> {
+    let entry = SuppressionEntry::parse(&form.entry).map_err(SuppressionError::ValidationError)?;
+    suppress(&state.db_pool, &entry, SuppressionReason::Manual)
+        .await
+        .context("Failed to add a suppression.")?;
+    Ok(StatusCode::OK)
+}
+
+#[tracing::instrument(name = "Remove a suppression", skip(state))]
+pub async fn remove_suppression(
+    State(state): State<AppState>,
+    Path(entry): Path<String>,
+) -> Result<StatusCode, SuppressionError> {
+    let entry = SuppressionEntry::parse(&entry).map_err(SuppressionError::ValidationError)?;
+    let removed = unsuppress(&state.db_pool, &entry)
+        .await
+        .context("Failed to remove a suppression.")?;
+    if !removed {
+        return Err(SuppressionError::NotFound);
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn export_suppressions(
+    State(state): State<AppState>,
+) -> Result<Response, SuppressionError> {
+    let suppressions = list_suppressions(&state.db_pool)
+        .await
+        .context("Failed to list suppressions.")?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                r#"attachment; filename="suppressions.csv""#,
+            ),
+        ],
+        to_csv(&suppressions),
+    )
+        .into_response())
+}
+
+/// Add every entry of a CSV body, as produced by [`export_suppressions`].
+///
+/// Nothing is imported if a line is invalid.
+#[tracing::instrument(name = "Import suppressions", skip_all)]
+pub async fn import_suppressions(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<String, SuppressionError> {
+    let entries = parse_csv(&body).map_err(SuppressionError::ValidationError)?;
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    for (entry, reason) in &entries {
+        suppress(&mut *transaction, entry, *reason)
+            .await
+            .context("Failed to add a suppression.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to import suppressions.")?;
+    Ok(format!("Imported {} entries.", entries.len()))
+}
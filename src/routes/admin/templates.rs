@@ -0,0 +1,63 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use minijinja::{context, Value};
+
+use crate::{
+    email_templates::TemplateError,
+    startup::{AppState, ApplicationBaseUrl},
+    utils::{e500, html_escape},
+};
+
+/// Render an email template with sample data.
+///
+/// Templates are read again, so that an edit can be checked before the cache expires.
+pub async fn preview_template(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Html<String>, Response> {
+    state.email_templates.invalidate();
+    let email = match state
+        .email_templates
+        .render(&name, sample_context(&state.base_url))
+        .await
+    {
+        Ok(email) => email,
+        Err(TemplateError::NotFound(_)) => return Err(StatusCode::NOT_FOUND.into_response()),
+        Err(e) => return Err(e500(e)),
+    };
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preview of {name}</title>
+</head>
+<body>
+    <p>Subject: <strong>{subject}</strong></p>
+    <iframe sandbox srcdoc="{html}" width="100%" height="500"></iframe>
+    <pre>{text}</pre>
+</body>
+</html>"#,
+        name = html_escape(&name),
+        subject = html_escape(&email.subject),
+        html = html_escape(&email.html),
+        text = html_escape(&email.text),
+    )))
+}
+
+/// The variables our templates use, with made-up values.
+fn sample_context(base_url: &ApplicationBaseUrl) -> Value {
+    let base_url = base_url.0.trim_end_matches('/');
+    context! {
+        subscriber_name => "Ada Lovelace",
+        confirmation_link => format!("{}/subscriptions/confirm?subscription_token=sample", base_url),
+        unsubscribe_link => format!("{}/subscriptions/unsubscribe?token=sample", base_url),
+        open_tracking_link => format!("{}/t/o/sample.gif", base_url),
+        issue_title => "Sample issue",
+        issue_html => Value::from_safe_string("<h1>Sample issue</h1><p>Hello, world!</p>".into()),
+        issue_text => "Sample issue\n\nHello, world!",
+    }
+}
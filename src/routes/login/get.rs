@@ -0,0 +1,33 @@
+use axum::response::Html;
+
+pub async fn login_form() -> Html<String> {
+    Html(render_login_form(None))
+}
+
+/// `error` must be static text: it is embedded in the page as-is.
+pub(crate) fn render_login_form(error: Option<&str>) -> String {
+    let error_html = error
+        .map(|error| format!("<p><i>{}</i></p>", error))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#,
+    )
+}
@@ -0,0 +1,78 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use axum_extra::extract::SignedCookieJar;
+use secrecy::SecretString;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    routes::{error_chain_fmt, login::get::render_login_form},
+    startup::AppState,
+};
+
+#[derive(serde::Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: SecretString,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for LoginError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            LoginError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            LoginError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to log in"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status, Html(render_login_form(Some(&self.to_string())))).into_response()
+    }
+}
+
+#[tracing::instrument(
+    skip(state, form),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+    Form(form): Form<LoginFormData>,
+) -> Result<(SignedCookieJar, Redirect), LoginError> {
+    let credentials = Credentials {
+        username: form.username,
+        password: form.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, &state.db_pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let secure = state.base_url.0.starts_with("https://");
+    let session_cookie = state.sessions.create(user_id, secure).await?;
+    Ok((jar.add(session_cookie), Redirect::to("/admin/dashboard")))
+}
@@ -0,0 +1,19 @@
+mod admin;
+mod login;
+mod newsletter_links;
+mod prometheus;
+mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
+
+pub use admin::*;
+pub use login::*;
+pub use newsletter_links::*;
+pub use prometheus::*;
+pub use subscriptions::*;
+pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
@@ -0,0 +1,62 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    startup::{AppState, ApplicationBaseUrl},
+    utils::e500,
+};
+
+/// Where emails send readers to follow the link of an issue with `link_index`.
+pub fn issue_link(
+    base_url: &ApplicationBaseUrl,
+    newsletter_issue_id: Uuid,
+    link_index: i32,
+) -> String {
+    format!(
+        "{}/newsletters/{}/links/{}",
+        base_url.0.trim_end_matches('/'),
+        newsletter_issue_id,
+        link_index
+    )
+}
+
+/// Send the reader of an issue on to the link they clicked.
+#[tracing::instrument(name = "Follow a newsletter link", skip(state))]
+pub async fn follow_issue_link(
+    State(state): State<AppState>,
+    Path((newsletter_issue_id, link_index)): Path<(Uuid, i32)>,
+) -> Result<Redirect, Response> {
+    match get_link(&state.db_pool, newsletter_issue_id, link_index)
+        .await
+        .map_err(e500)?
+    {
+        Some(url) => Ok(Redirect::to(&url)),
+        None => Err(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+pub(super) async fn get_link(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    link_index: i32,
+) -> Result<Option<String>, anyhow::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT url
+        FROM newsletter_issue_links
+        WHERE newsletter_issue_id = $1 AND link_index = $2
+        "#,
+    )
+    .bind(newsletter_issue_id)
+    .bind(link_index)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a newsletter link.")
+}
@@ -0,0 +1,19 @@
+use std::sync::OnceLock;
+
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+static PROMETHEUS: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the Prometheus recorder on first use. Metrics recorded before that are lost.
+pub fn prometheus_handle() -> &'static PrometheusHandle {
+    PROMETHEUS.get_or_init(|| {
+        PrometheusBuilder::new()
+            .install_recorder()
+            .expect("Failed to install the Prometheus recorder")
+    })
+}
+
+/// Metrics in the Prometheus text format.
+pub async fn export_metrics() -> String {
+    prometheus_handle().render()
+}
@@ -0,0 +1,508 @@
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Form,
+};
+use chrono::Utc;
+use minijinja::context;
+use sqlx::{Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionToken},
+    email_client::EmailClient,
+    email_templates::EmailTemplates,
+    startup::{AppState, ApplicationBaseUrl},
+    suppression::is_suppressed,
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+    name: String,
+}
+
+impl TryFrom<FormData> for NewSubscriber {
+    type Error = String;
+
+    fn try_from(value: FormData) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(value.name)?;
+        let email = SubscriberEmail::parse(value.email)?;
+        Ok(Self { email, name })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for SubscribeError {
+    fn into_response(self) -> Response {
+        match self {
+            SubscribeError::ValidationError(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            SubscribeError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to add a new subscriber"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Adding a new subscriber",
+    skip(state, form),
+    fields(
+        subscriber_email = %form.email,
+        subscriber_name = %form.name
+    )
+)]
+pub async fn subscribe(
+    State(state): State<AppState>,
+    Form(form): Form<FormData>,
+) -> Result<StatusCode, SubscribeError> {
+    let new_subscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscription_token = subscription_token(&mut transaction, &new_subscriber).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    let Some(subscription_token) = subscription_token else {
+        // Same response as for a new subscriber: whether an address is subscribed is
+        // private.
+        tracing::info!("Not sending a confirmation email to an address already confirmed");
+        return Ok(StatusCode::OK);
+    };
+    if is_suppressed(&state.db_pool, &new_subscriber.email)
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        // Same response as for anyone else: whether an address is suppressed is private.
+        tracing::info!("Not sending a confirmation email to a suppressed address");
+        return Ok(StatusCode::OK);
+    }
+    send_confirmation_email(
+        &state.email_client.load(),
+        &state.email_templates,
+        new_subscriber,
+        &state.base_url,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
+    Ok(StatusCode::OK)
+}
+
+/// The token to send in the confirmation email, `None` if the subscriber has already
+/// confirmed.
+///
+/// A new subscriber gets a new token. Someone who subscribes again before confirming
+/// gets the token they were sent the first time, so that either email works. Someone
+/// who unsubscribed, or whose address was suppressed, starts over: they are pending
+/// again and get a new token.
+async fn subscription_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<SubscriptionToken>, SubscribeError> {
+    if let Some(subscriber_id) = insert_subscriber(transaction, new_subscriber)
+        .await
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        return new_subscription_token(transaction, subscriber_id).await;
+    }
+    let (subscriber_id, status) = get_existing_subscriber(transaction, &new_subscriber.email)
+        .await
+        .context("Failed to retrieve an existing subscriber.")?;
+    match status.as_str() {
+        "confirmed" => return Ok(None),
+        "pending_confirmation" => {
+            let subscription_token = get_pending_subscription_token(transaction, subscriber_id)
+                .await
+                .context("Failed to retrieve the confirmation token of an existing subscriber.")?;
+            if subscription_token.is_some() {
+                return Ok(subscription_token);
+            }
+        }
+        _ => resubscribe(transaction, subscriber_id, new_subscriber)
+            .await
+            .context("Failed to mark a former subscriber as pending again.")?,
+    }
+    new_subscription_token(transaction, subscriber_id).await
+}
+
+async fn new_subscription_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriptionToken>, SubscribeError> {
+    let subscription_token = SubscriptionToken::generate();
+    store_token(transaction, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store the confirmation token for a new subscriber.")?;
+    Ok(Some(subscription_token))
+}
+
+/// Returns `None` if there already is a subscriber with this email address.
+#[tracing::instrument(
+    name = "Saving new subscriber details in the database",
+    skip(transaction, new_subscriber)
+)]
+pub async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = Uuid::new_v4();
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT ((lower(email))) DO NOTHING
+        "#,
+    )
+    .bind(subscriber_id)
+    .bind(new_subscriber.email.as_ref())
+    .bind(new_subscriber.name.as_ref())
+    .bind(Utc::now())
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+    Ok((inserted > 0).then_some(subscriber_id))
+}
+
+/// The id and status of the subscriber with this email address, locked until the
+/// transaction ends.
+#[tracing::instrument(skip(transaction, email))]
+async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let row = sqlx::query(
+        r#"SELECT id, status FROM subscriptions WHERE lower(email) = lower($1) FOR UPDATE"#,
+    )
+    .bind(email.as_ref())
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok((row.try_get("id")?, row.try_get("status")?))
+}
+
+/// A confirmation token of a subscriber who has not confirmed yet.
+#[tracing::instrument(skip(transaction))]
+async fn get_pending_subscription_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriptionToken>, anyhow::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT subscription_token FROM subscription_tokens
+        WHERE subscriber_id = $1
+        LIMIT 1
+        "#,
+    )
+    .bind(subscriber_id)
+    .fetch_optional(&mut **transaction)
+    .await?;
+    row.map(|row| {
+        SubscriptionToken::parse(row.try_get("subscription_token")?).map_err(anyhow::Error::msg)
+    })
+    .transpose()
+}
+
+/// Marks a former subscriber as pending again, as if they had just signed up.
+///
+/// Their old tokens are dropped, so that a confirmation link from before they left
+/// cannot confirm the new subscription.
+#[tracing::instrument(skip(transaction, new_subscriber))]
+async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#)
+        .bind(subscriber_id)
+        .execute(&mut **transaction)
+        .await?;
+    sqlx::query(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation', suppressed_from = NULL, name = $2,
+            subscribed_at = $3
+        WHERE id = $1
+        "#,
+    )
+    .bind(subscriber_id)
+    .bind(new_subscriber.name.as_ref())
+    .bind(Utc::now())
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(transaction, subscription_token)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &SubscriptionToken,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        VALUES ($1, $2)
+        "#,
+    )
+    .bind(subscription_token.as_ref())
+    .bind(subscriber_id)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a confirmation email to a new subscriber",
+    skip(
+        email_client,
+        email_templates,
+        new_subscriber,
+        base_url,
+        subscription_token
+    )
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    new_subscriber: NewSubscriber,
+    base_url: &ApplicationBaseUrl,
+    subscription_token: &SubscriptionToken,
+) -> Result<(), anyhow::Error> {
+    let email = email_templates
+        .render(
+            "confirmation",
+            context! {
+                subscriber_name => new_subscriber.name.as_ref(),
+                confirmation_link => confirmation_link(base_url, subscription_token),
+            },
+        )
+        .await?;
+    email_client
+        .send_email(
+            &new_subscriber.email,
+            &email.subject,
+            &email.html,
+            &email.text,
+        )
+        .await?;
+    Ok(())
+}
+
+fn confirmation_link(
+    base_url: &ApplicationBaseUrl,
+    subscription_token: &SubscriptionToken,
+) -> String {
+    format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url.0.trim_end_matches('/'),
+        subscription_token.as_ref()
+    )
+}
+
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{confirmation_link, subscription_token, FormData, SubscribeError};
+    use crate::domain::{NewSubscriber, SubscriptionToken};
+    use crate::routes::{
+        confirm_subscriber, get_subscriber_id_from_token, mark_subscriber_as_unsubscribed,
+    };
+    use crate::startup::ApplicationBaseUrl;
+    use axum::{http::StatusCode, response::IntoResponse};
+    use claims::{assert_err, assert_none, assert_ok, assert_some};
+    use sqlx::PgPool;
+
+    fn form(name: &str, email: &str) -> FormData {
+        FormData {
+            name: name.into(),
+            email: email.into(),
+        }
+    }
+
+    #[test]
+    fn valid_form_data_is_converted_into_a_new_subscriber() {
+        let outcome: Result<NewSubscriber, _> =
+            form("le guin", "ursula_le_guin@gmail.com").try_into();
+        assert_ok!(outcome);
+    }
+
+    #[test]
+    fn form_data_with_invalid_fields_is_rejected() {
+        let test_cases = vec![
+            (form("", "ursula_le_guin@gmail.com"), "empty name"),
+            (form("Ursula", ""), "empty email"),
+            (form("Ursula", "definitely-not-an-email"), "invalid email"),
+        ];
+
+        for (invalid_form, description) in test_cases {
+            let outcome: Result<NewSubscriber, String> = invalid_form.try_into();
+            assert_err!(&outcome, "Accepted a form with {}.", description);
+        }
+    }
+
+    #[test]
+    fn validation_errors_are_reported_as_bad_requests() {
+        let response = SubscribeError::ValidationError("invalid".into()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn unexpected_errors_are_reported_as_internal_server_errors() {
+        let response = SubscribeError::UnexpectedError(anyhow::anyhow!("boom")).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn confirmation_link_points_to_the_confirm_endpoint() {
+        let token = SubscriptionToken::generate();
+        let base_url = ApplicationBaseUrl("http://localhost:8000/".into());
+
+        let link = confirmation_link(&base_url, &token);
+
+        assert_eq!(
+            link,
+            format!(
+                "http://localhost:8000/subscriptions/confirm?subscription_token={}",
+                token.as_ref()
+            )
+        );
+    }
+
+    async fn subscribe(pool: &PgPool) -> Option<SubscriptionToken> {
+        subscribe_as(pool, "ursula_le_guin@gmail.com").await
+    }
+
+    async fn subscribe_as(pool: &PgPool, email: &str) -> Option<SubscriptionToken> {
+        let new_subscriber: NewSubscriber = form("Ursula", email).try_into().unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        let token = assert_ok!(subscription_token(&mut transaction, &new_subscriber).await);
+        transaction.commit().await.unwrap();
+        token
+    }
+
+    #[sqlx::test]
+    async fn subscribing_again_before_confirming_resends_the_same_token(pool: PgPool) {
+        let first = assert_some!(subscribe(&pool).await);
+        let second = assert_some!(subscribe(&pool).await);
+
+        assert_eq!(second.as_ref(), first.as_ref());
+        let subscribers: i64 = sqlx::query_scalar("SELECT count(*) FROM subscriptions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(subscribers, 1);
+    }
+
+    #[sqlx::test]
+    async fn addresses_differing_only_in_case_are_the_same_subscriber(pool: PgPool) {
+        let first = assert_some!(subscribe(&pool).await);
+        let second = assert_some!(subscribe_as(&pool, "Ursula_Le_Guin@Gmail.com").await);
+
+        assert_eq!(second.as_ref(), first.as_ref());
+        let subscribers: i64 = sqlx::query_scalar("SELECT count(*) FROM subscriptions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(subscribers, 1);
+    }
+
+    #[sqlx::test]
+    async fn confirmed_subscribers_are_not_sent_another_token(pool: PgPool) {
+        assert_some!(subscribe(&pool).await);
+        sqlx::query("UPDATE subscriptions SET status = 'confirmed'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_none!(subscribe(&pool).await);
+    }
+
+    async fn status(pool: &PgPool) -> String {
+        sqlx::query_scalar("SELECT status FROM subscriptions")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn unsubscribed_subscribers_can_subscribe_again(pool: PgPool) {
+        let first = assert_some!(subscribe(&pool).await);
+        let subscriber_id = get_subscriber_id_from_token(&pool, &first)
+            .await
+            .unwrap()
+            .unwrap();
+        confirm_subscriber(&pool, subscriber_id).await.unwrap();
+        mark_subscriber_as_unsubscribed(&pool, subscriber_id)
+            .await
+            .unwrap();
+
+        let second = assert_some!(subscribe(&pool).await);
+        assert_ne!(second.as_ref(), first.as_ref());
+        assert_eq!(status(&pool).await, "pending_confirmation");
+        // The old confirmation link does not confirm the new subscription.
+        assert_none!(get_subscriber_id_from_token(&pool, &first).await.unwrap());
+
+        let resubscribed_id = get_subscriber_id_from_token(&pool, &second)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resubscribed_id, subscriber_id);
+        confirm_subscriber(&pool, resubscribed_id).await.unwrap();
+        assert_eq!(status(&pool).await, "confirmed");
+    }
+
+    #[sqlx::test]
+    async fn suppressed_subscribers_can_subscribe_again(pool: PgPool) {
+        assert_some!(subscribe(&pool).await);
+        sqlx::query(
+            "UPDATE subscriptions SET status = 'suppressed', suppressed_from = 'confirmed'",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert_some!(subscribe(&pool).await);
+        assert_eq!(status(&pool).await, "pending_confirmation");
+    }
+}
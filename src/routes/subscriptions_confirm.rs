@@ -0,0 +1,98 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{domain::SubscriptionToken, routes::error_chain_fmt, startup::AppState};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmationError {
+    #[error("{0}")]
+    MalformedToken(String),
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for ConfirmationError {
+    fn into_response(self) -> Response {
+        match self {
+            ConfirmationError::MalformedToken(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            ConfirmationError::UnknownToken => {
+                (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+            }
+            ConfirmationError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to confirm a pending subscriber"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(state, parameters))]
+pub async fn confirm(
+    State(state): State<AppState>,
+    Query(parameters): Query<Parameters>,
+) -> Result<StatusCode, ConfirmationError> {
+    let subscription_token = SubscriptionToken::parse(parameters.subscription_token)
+        .map_err(ConfirmationError::MalformedToken)?;
+    let subscriber_id = get_subscriber_id_from_token(&state.db_pool, &subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber id associated with the provided token.")?
+        .ok_or(ConfirmationError::UnknownToken)?;
+    confirm_subscriber(&state.db_pool, subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `confirmed`.")?;
+    Ok(StatusCode::OK)
+}
+
+/// Marks the subscriber as confirmed.
+///
+/// Running it against an already confirmed subscriber leaves the row untouched,
+/// so following the confirmation link twice is harmless.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool))]
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1 AND status = 'pending_confirmation'"#,
+    )
+    .bind(subscriber_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, subscription_token))]
+pub async fn get_subscriber_id_from_token(
+    pool: &PgPool,
+    subscription_token: &SubscriptionToken,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = sqlx::query_scalar(
+        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+    )
+    .bind(subscription_token.as_ref())
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber_id)
+}
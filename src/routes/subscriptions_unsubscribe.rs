@@ -0,0 +1,271 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    email_client::EmailHeader,
+    routes::error_chain_fmt,
+    signing::{sign, verify},
+    startup::{AppState, ApplicationBaseUrl, HmacSecret},
+    utils::html_escape,
+};
+
+/// Proof that whoever holds it may unsubscribe `subscriber_id`, optionally until `expires_at`.
+#[derive(Debug, PartialEq)]
+pub struct UnsubscribeToken {
+    subscriber_id: Uuid,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl UnsubscribeToken {
+    pub fn new(subscriber_id: Uuid, expires_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            subscriber_id,
+            // Only whole seconds survive encoding.
+            expires_at: expires_at
+                .and_then(|expires_at| DateTime::from_timestamp(expires_at.timestamp(), 0)),
+        }
+    }
+
+    fn expiry_field(&self) -> String {
+        self.expires_at
+            .map(|expires_at| expires_at.timestamp().to_string())
+            .unwrap_or_default()
+    }
+
+    fn signed_message(&self) -> String {
+        format!("unsubscribe:{}:{}", self.subscriber_id, self.expiry_field())
+    }
+
+    /// Serialise as `<subscriber_id>.<expiry unix timestamp, or empty>.<signature>`.
+    pub fn encode(&self, hmac_secret: &HmacSecret) -> String {
+        format!(
+            "{}.{}.{}",
+            self.subscriber_id,
+            self.expiry_field(),
+            sign(hmac_secret, &self.signed_message())
+        )
+    }
+
+    pub fn decode(
+        token: &str,
+        hmac_secret: &HmacSecret,
+        now: DateTime<Utc>,
+    ) -> Result<Self, UnsubscribeError> {
+        let mut parts = token.split('.');
+        let (Some(subscriber_id), Some(expires_at), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(UnsubscribeError::InvalidToken);
+        };
+        let subscriber_id = subscriber_id
+            .parse()
+            .map_err(|_| UnsubscribeError::InvalidToken)?;
+        let expires_at = match expires_at {
+            "" => None,
+            timestamp => Some(
+                timestamp
+                    .parse()
+                    .ok()
+                    .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+                    .ok_or(UnsubscribeError::InvalidToken)?,
+            ),
+        };
+        let token = Self {
+            subscriber_id,
+            expires_at,
+        };
+        if !verify(hmac_secret, &token.signed_message(), signature) {
+            return Err(UnsubscribeError::InvalidToken);
+        }
+        if token.expires_at.is_some_and(|expires_at| expires_at < now) {
+            return Err(UnsubscribeError::InvalidToken);
+        }
+        Ok(token)
+    }
+}
+
+/// The signed link a recipient follows, or a mailbox provider POSTs to, to unsubscribe.
+pub fn unsubscribe_link(
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    token: &UnsubscribeToken,
+) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url.0.trim_end_matches('/'),
+        token.encode(hmac_secret)
+    )
+}
+
+/// RFC 8058 one-click unsubscribe headers pointing at `unsubscribe_link`.
+pub fn list_unsubscribe_headers(unsubscribe_link: &str) -> Vec<EmailHeader> {
+    vec![
+        EmailHeader {
+            name: "List-Unsubscribe".into(),
+            value: format!("<{}>", unsubscribe_link),
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        },
+    ]
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid or has expired.")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for UnsubscribeError {
+    fn into_response(self) -> Response {
+        match self {
+            UnsubscribeError::InvalidToken => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            UnsubscribeError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to unsubscribe a subscriber"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// Ask for confirmation rather than unsubscribing straight away: link scanners and
+/// prefetchers follow GET links, mailbox providers use the one-click POST.
+pub async fn unsubscribe_form(
+    State(state): State<AppState>,
+    Query(parameters): Query<UnsubscribeParameters>,
+) -> Result<Html<String>, UnsubscribeError> {
+    UnsubscribeToken::decode(&parameters.token, &state.hmac_secret, Utc::now())?;
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <form action="/subscriptions/unsubscribe?token={}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe from the newsletter</button>
+    </form>
+</body>
+</html>"#,
+        html_escape(&parameters.token)
+    )))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(state, parameters))]
+pub async fn unsubscribe(
+    State(state): State<AppState>,
+    Query(parameters): Query<UnsubscribeParameters>,
+) -> Result<&'static str, UnsubscribeError> {
+    let token = UnsubscribeToken::decode(&parameters.token, &state.hmac_secret, Utc::now())?;
+    mark_subscriber_as_unsubscribed(&state.db_pool, token.subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `unsubscribed`.")?;
+    Ok("You have been unsubscribed.")
+}
+
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE subscriptions SET status = 'unsubscribed', suppressed_from = NULL WHERE id = $1"#,
+    )
+    .bind(subscriber_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use crate::startup::HmacSecret;
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok};
+    use secrecy::SecretString;
+    use uuid::Uuid;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(SecretString::from("secret"))
+    }
+
+    #[test]
+    fn a_token_without_expiry_round_trips() {
+        let token = UnsubscribeToken::new(Uuid::new_v4(), None);
+        let decoded = UnsubscribeToken::decode(&token.encode(&secret()), &secret(), Utc::now());
+        assert_eq!(assert_ok!(decoded), token);
+    }
+
+    #[test]
+    fn a_token_is_valid_until_it_expires() {
+        let now = Utc::now();
+        let token = UnsubscribeToken::new(Uuid::new_v4(), Some(now + Duration::days(1)));
+        let encoded = token.encode(&secret());
+
+        assert_ok!(UnsubscribeToken::decode(&encoded, &secret(), now));
+        assert_err!(UnsubscribeToken::decode(
+            &encoded,
+            &secret(),
+            now + Duration::days(2)
+        ));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = UnsubscribeToken::new(Uuid::new_v4(), None).encode(&secret());
+        let signature = token.rsplit('.').next().unwrap();
+        let forged = format!("{}..{}", Uuid::new_v4(), signature);
+        assert_err!(UnsubscribeToken::decode(&forged, &secret(), Utc::now()));
+    }
+
+    #[test]
+    fn an_extended_expiry_is_rejected() {
+        let now = Utc::now();
+        let token = UnsubscribeToken::new(Uuid::new_v4(), Some(now)).encode(&secret());
+        let mut parts = token.split('.');
+        let (subscriber_id, _, signature) = (
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+        );
+        let forged = format!("{}..{}", subscriber_id, signature);
+        assert_err!(UnsubscribeToken::decode(&forged, &secret(), now));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "a.b", "a.b.c.d", "not-a-uuid..abcd"] {
+            assert_err!(UnsubscribeToken::decode(token, &secret(), Utc::now()));
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::newsletter_links::{get_link, issue_link};
+use crate::{
+    routes::error_chain_fmt,
+    signing::{sign, verify},
+    startup::{ApplicationBaseUrl, HmacSecret},
+};
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+/// Identifies who opened an issue, or which of its links they followed.
+///
+/// Signed, so that a token cannot be made up to record events for someone else or to
+/// redirect to a link that is not part of the issue.
+#[derive(Debug, PartialEq)]
+pub struct TrackingToken {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    /// `None` for opens.
+    link_index: Option<i32>,
+}
+
+impl TrackingToken {
+    pub fn open(newsletter_issue_id: Uuid, subscriber_id: Uuid) -> Self {
+        Self {
+            newsletter_issue_id,
+            subscriber_id,
+            link_index: None,
+        }
+    }
+
+    pub fn click(newsletter_issue_id: Uuid, subscriber_id: Uuid, link_index: i32) -> Self {
+        Self {
+            newsletter_issue_id,
+            subscriber_id,
+            link_index: Some(link_index),
+        }
+    }
+
+    fn link_field(&self) -> String {
+        self.link_index
+            .map(|link_index| link_index.to_string())
+            .unwrap_or_default()
+    }
+
+    fn signed_message(&self) -> String {
+        format!(
+            "track:{}:{}:{}",
+            self.newsletter_issue_id,
+            self.subscriber_id,
+            self.link_field()
+        )
+    }
+
+    /// Serialise as `<issue_id>.<subscriber_id>.<link index, or empty>.<signature>`.
+    pub fn encode(&self, hmac_secret: &HmacSecret) -> String {
+        format!(
+            "{}.{}.{}.{}",
+            self.newsletter_issue_id,
+            self.subscriber_id,
+            self.link_field(),
+            sign(hmac_secret, &self.signed_message())
+        )
+    }
+
+    pub fn decode(token: &str, hmac_secret: &HmacSecret) -> Result<Self, TrackingError> {
+        let mut parts = token.split('.');
+        let (
+            Some(newsletter_issue_id),
+            Some(subscriber_id),
+            Some(link_index),
+            Some(signature),
+            None,
+        ) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        )
+        else {
+            return Err(TrackingError::InvalidToken);
+        };
+        let token = Self {
+            newsletter_issue_id: newsletter_issue_id
+                .parse()
+                .map_err(|_| TrackingError::InvalidToken)?,
+            subscriber_id: subscriber_id
+                .parse()
+                .map_err(|_| TrackingError::InvalidToken)?,
+            link_index: match link_index {
+                "" => None,
+                link_index => Some(
+                    link_index
+                        .parse()
+                        .map_err(|_| TrackingError::InvalidToken)?,
+                ),
+            },
+        };
+        if !verify(hmac_secret, &token.signed_message(), signature) {
+            return Err(TrackingError::InvalidToken);
+        }
+        Ok(token)
+    }
+}
+
+/// The image that records an open when an email client loads it.
+pub fn open_tracking_link(
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    token: &TrackingToken,
+) -> String {
+    format!(
+        "{}/t/o/{}.gif",
+        base_url.0.trim_end_matches('/'),
+        token.encode(hmac_secret)
+    )
+}
+
+/// The link that records a click before redirecting to the link of the issue.
+pub fn click_tracking_link(
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    token: &TrackingToken,
+) -> String {
+    format!(
+        "{}/t/c/{}",
+        base_url.0.trim_end_matches('/'),
+        token.encode(hmac_secret)
+    )
+}
+
+/// Point the links of an issue in `content` to the click-tracking endpoint, on behalf of
+/// `subscriber_id`.
+pub fn track_clicks(
+    content: &str,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    link_count: i32,
+) -> String {
+    let mut content = content.to_string();
+    // Highest index first, so that `.../links/1` is not replaced inside `.../links/12`.
+    for link_index in (0..link_count).rev() {
+        let link = issue_link(base_url, newsletter_issue_id, link_index);
+        let token = TrackingToken::click(newsletter_issue_id, subscriber_id, link_index);
+        let tracked_link = click_tracking_link(base_url, hmac_secret, &token);
+        content = content.replace(&link, &tracked_link);
+    }
+    content
+}
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("The tracking link is invalid.")]
+    InvalidToken,
+    #[error("The link does not exist.")]
+    UnknownLink,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for TrackingError {
+    fn into_response(self) -> Response {
+        match self {
+            TrackingError::InvalidToken => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            TrackingError::UnknownLink => StatusCode::NOT_FOUND.into_response(),
+            TrackingError::UnexpectedError(_) => {
+                tracing::error!(
+                    error.cause_chain = ?self,
+                    error.message = %self,
+                    "Failed to follow a tracking link"
+                );
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(name = "Track an open", skip(pool, hmac_secret, headers))]
+pub async fn track_open(
+    State(pool): State<PgPool>,
+    State(hmac_secret): State<HmacSecret>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, TrackingError> {
+    let token = token
+        .strip_suffix(".gif")
+        .ok_or(TrackingError::InvalidToken)?;
+    let token = TrackingToken::decode(token, &hmac_secret)?;
+    if token.link_index.is_some() {
+        return Err(TrackingError::InvalidToken);
+    }
+    record_event(&pool, &token, user_agent(&headers)).await;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/gif"),
+            // Every load of the email should reach us.
+            (header::CACHE_CONTROL, "no-store, max-age=0"),
+        ],
+        PIXEL,
+    )
+        .into_response())
+}
+
+#[tracing::instrument(name = "Track a click", skip(pool, hmac_secret, headers))]
+pub async fn track_click(
+    State(pool): State<PgPool>,
+    State(hmac_secret): State<HmacSecret>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Redirect, TrackingError> {
+    let token = TrackingToken::decode(&token, &hmac_secret)?;
+    let Some(link_index) = token.link_index else {
+        return Err(TrackingError::InvalidToken);
+    };
+    let url = get_link(&pool, token.newsletter_issue_id, link_index)
+        .await?
+        .ok_or(TrackingError::UnknownLink)?;
+    record_event(&pool, &token, user_agent(&headers)).await;
+    Ok(Redirect::to(&url))
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+}
+
+/// Record the event, if tracking is enabled for the issue.
+///
+/// Failing to record an event must not keep the reader from their link, or break the
+/// rendering of the email: errors are only logged.
+async fn record_event(pool: &PgPool, token: &TrackingToken, user_agent: Option<&str>) {
+    if let Err(e) = insert_event(pool, token, user_agent).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record a newsletter event"
+        );
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn insert_event(
+    pool: &PgPool,
+    token: &TrackingToken,
+    user_agent: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let kind = match token.link_index {
+        Some(_) => "click",
+        None => "open",
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO newsletter_events (
+            newsletter_issue_id,
+            subscriber_id,
+            kind,
+            link_index,
+            user_agent,
+            occurred_at
+        )
+        SELECT newsletter_issue_id, $2, $3, $4, $5, $6
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND tracking_enabled
+        "#,
+    )
+    .bind(token.newsletter_issue_id)
+    .bind(token.subscriber_id)
+    .bind(kind)
+    .bind(token.link_index)
+    .bind(user_agent)
+    .bind(Utc::now())
+    .execute(pool)
+    .await
+    .context("Failed to insert a newsletter event.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{click_tracking_link, track_click, track_clicks, track_open, TrackingToken};
+    use crate::routes::issue_link;
+    use crate::startup::{ApplicationBaseUrl, HmacSecret};
+    use axum::{
+        extract::{Path, State},
+        http::{header, HeaderMap, HeaderValue},
+        response::IntoResponse,
+    };
+    use claims::{assert_err, assert_ok};
+    use secrecy::SecretString;
+    use sqlx::{PgPool, Row};
+    use uuid::Uuid;
+
+    fn secret() -> HmacSecret {
+        HmacSecret(SecretString::from("secret"))
+    }
+
+    #[test]
+    fn open_and_click_tokens_round_trip() {
+        for token in [
+            TrackingToken::open(Uuid::new_v4(), Uuid::new_v4()),
+            TrackingToken::click(Uuid::new_v4(), Uuid::new_v4(), 3),
+        ] {
+            let decoded = TrackingToken::decode(&token.encode(&secret()), &secret());
+            assert_eq!(assert_ok!(decoded), token);
+        }
+    }
+
+    #[test]
+    fn a_token_for_another_link_is_rejected() {
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        let token = TrackingToken::click(issue_id, subscriber_id, 0).encode(&secret());
+        let signature = token.rsplit('.').next().unwrap();
+
+        let forged = format!("{}.{}.1.{}", issue_id, subscriber_id, signature);
+        assert_err!(TrackingToken::decode(&forged, &secret()));
+    }
+
+    #[test]
+    fn an_open_token_cannot_be_turned_into_a_click() {
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        let token = TrackingToken::open(issue_id, subscriber_id).encode(&secret());
+        let signature = token.rsplit('.').next().unwrap();
+
+        let forged = format!("{}.{}.0.{}", issue_id, subscriber_id, signature);
+        assert_err!(TrackingToken::decode(&forged, &secret()));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "a.b.c", "a.b.c.d.e", "not-a-uuid.not-a-uuid..abcd"] {
+            assert_err!(TrackingToken::decode(token, &secret()));
+        }
+    }
+
+    fn base_url() -> ApplicationBaseUrl {
+        ApplicationBaseUrl("https://example.com".into())
+    }
+
+    #[test]
+    fn links_are_tracked_without_clobbering_longer_indices() {
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        let content = format!(
+            "{} {}",
+            issue_link(&base_url(), issue_id, 1),
+            issue_link(&base_url(), issue_id, 12)
+        );
+
+        let tracked = track_clicks(
+            &content,
+            &base_url(),
+            &secret(),
+            issue_id,
+            subscriber_id,
+            13,
+        );
+
+        let tracked_link = |link_index| {
+            let token = TrackingToken::click(issue_id, subscriber_id, link_index);
+            click_tracking_link(&base_url(), &secret(), &token)
+        };
+        assert_eq!(tracked, format!("{} {}", tracked_link(1), tracked_link(12)));
+    }
+
+    /// An issue with a single link, sent to a single subscriber.
+    async fn issue(pool: &PgPool, tracking_enabled: bool) -> (Uuid, Uuid) {
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, 'ursula_le_guin@gmail.com', 'Ursula', now(), 'confirmed')
+            "#,
+        )
+        .bind(subscriber_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id, title, text_content, html_content, published_at,
+                tracking_enabled
+            )
+            VALUES ($1, 'Issue #1', 'News', '<p>News</p>', now(), $2)
+            "#,
+        )
+        .bind(issue_id)
+        .bind(tracking_enabled)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO newsletter_issue_links (newsletter_issue_id, link_index, url)
+            VALUES ($1, 0, 'https://example.org')
+            "#,
+        )
+        .bind(issue_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        (issue_id, subscriber_id)
+    }
+
+    /// Open, then follow the link of the issue.
+    async fn open_and_click(pool: &PgPool, issue_id: Uuid, subscriber_id: Uuid) {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("Thunderbird"));
+        let open = TrackingToken::open(issue_id, subscriber_id).encode(&secret());
+        let click = TrackingToken::click(issue_id, subscriber_id, 0).encode(&secret());
+
+        let pixel = track_open(
+            State(pool.clone()),
+            State(secret()),
+            Path(format!("{}.gif", open)),
+            headers.clone(),
+        )
+        .await;
+        let redirect =
+            track_click(State(pool.clone()), State(secret()), Path(click), headers).await;
+
+        assert_eq!(
+            assert_ok!(pixel).headers()[header::CONTENT_TYPE],
+            "image/gif"
+        );
+        assert_eq!(
+            assert_ok!(redirect).into_response().headers()[header::LOCATION],
+            "https://example.org"
+        );
+    }
+
+    async fn events(pool: &PgPool) -> Vec<(String, Option<i32>, Option<String>)> {
+        sqlx::query(r#"SELECT kind, link_index, user_agent FROM newsletter_events ORDER BY id"#)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get("kind"),
+                    row.get("link_index"),
+                    row.get("user_agent"),
+                )
+            })
+            .collect()
+    }
+
+    #[sqlx::test]
+    async fn opens_and_clicks_are_recorded(pool: PgPool) {
+        let (issue_id, subscriber_id) = issue(&pool, true).await;
+
+        open_and_click(&pool, issue_id, subscriber_id).await;
+
+        let user_agent = Some("Thunderbird".to_string());
+        assert_eq!(
+            events(&pool).await,
+            vec![
+                ("open".to_string(), None, user_agent.clone()),
+                ("click".to_string(), Some(0), user_agent),
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn nothing_is_recorded_for_issues_without_tracking(pool: PgPool) {
+        let (issue_id, subscriber_id) = issue(&pool, false).await;
+
+        open_and_click(&pool, issue_id, subscriber_id).await;
+
+        assert!(events(&pool).await.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt::{Debug, Display};
+use std::time::Duration;
+
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+/// Resolves when the process receives SIGINT (Ctrl+C) or SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install the SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Wait for a task to exit and report why.
+///
+/// Once shutdown has started the task gets `shutdown_timeout` to wind down before it is
+/// aborted. A task exiting on its own triggers the shutdown of all the others.
+pub async fn supervise<E: Debug + Display>(
+    task_name: &str,
+    mut task: JoinHandle<Result<(), E>>,
+    shutdown: &CancellationToken,
+    shutdown_timeout: Duration,
+) {
+    let outcome = tokio::select! {
+        outcome = &mut task => outcome,
+        _ = async {
+            shutdown.cancelled().await;
+            tokio::time::sleep(shutdown_timeout).await;
+        } => {
+            tracing::warn!(
+                "{} did not stop within {:?}, aborting it",
+                task_name,
+                shutdown_timeout
+            );
+            task.abort();
+            task.await
+        }
+    };
+    report_exit(task_name, outcome);
+    shutdown.cancel();
+}
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::supervise;
+
+    const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Sets its flag when dropped, as an aborted task's future is.
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_task_exiting_on_its_own_shuts_the_others_down() {
+        let shutdown = CancellationToken::new();
+        let exiting = tokio::spawn(async { Err::<(), _>("Lost the database") });
+        let waiting = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown.cancelled().await;
+                Ok::<(), String>(())
+            }
+        });
+
+        let supervised = async {
+            tokio::join!(
+                supervise("Exiting", exiting, &shutdown, SHUTDOWN_TIMEOUT),
+                supervise("Waiting", waiting, &shutdown, SHUTDOWN_TIMEOUT),
+            )
+        };
+        tokio::time::timeout(Duration::from_secs(5), supervised)
+            .await
+            .expect("The waiting task was never told to shut down");
+
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn a_task_that_does_not_stop_in_time_is_aborted() {
+        let shutdown = CancellationToken::new();
+        let aborted = Arc::new(AtomicBool::new(false));
+        let stuck = tokio::spawn({
+            let flag = DropFlag(aborted.clone());
+            async move {
+                let _flag = flag;
+                std::future::pending::<Result<(), String>>().await
+            }
+        });
+
+        shutdown.cancel();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            supervise("Stuck", stuck, &shutdown, SHUTDOWN_TIMEOUT),
+        )
+        .await
+        .expect("The stuck task was not aborted");
+
+        assert!(aborted.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn a_task_that_stops_in_time_is_left_to_finish() {
+        let shutdown = CancellationToken::new();
+        let finished = Arc::new(AtomicBool::new(false));
+        let draining = tokio::spawn({
+            let shutdown = shutdown.clone();
+            let finished = finished.clone();
+            async move {
+                shutdown.cancelled().await;
+                tokio::time::sleep(SHUTDOWN_TIMEOUT / 4).await;
+                finished.store(true, Ordering::SeqCst);
+                Ok::<(), String>(())
+            }
+        });
+
+        shutdown.cancel();
+        supervise("Draining", draining, &shutdown, SHUTDOWN_TIMEOUT).await;
+
+        assert!(finished.load(Ordering::SeqCst));
+    }
+}
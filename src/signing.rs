@@ -0,0 +1,59 @@
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+
+use crate::startup::HmacSecret;
+
+fn mac(secret: &HmacSecret, message: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(message.as_bytes());
+    mac
+}
+
+/// Hex-encoded HMAC-SHA256 of `message`.
+pub fn sign(secret: &HmacSecret, message: &str) -> String {
+    hex::encode(mac(secret, message).finalize().into_bytes())
+}
+
+/// Check `signature`, as produced by [`sign`], in constant time.
+pub fn verify(secret: &HmacSecret, message: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    mac(secret, message).verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, verify};
+    use crate::startup::HmacSecret;
+    use secrecy::SecretString;
+
+    fn secret(s: &str) -> HmacSecret {
+        HmacSecret(SecretString::from(s))
+    }
+
+    #[test]
+    fn a_signature_is_verified_with_the_same_secret() {
+        let signature = sign(&secret("secret"), "message");
+        assert!(verify(&secret("secret"), "message", &signature));
+    }
+
+    #[test]
+    fn a_signature_is_rejected_for_a_different_message() {
+        let signature = sign(&secret("secret"), "message");
+        assert!(!verify(&secret("secret"), "another message", &signature));
+    }
+
+    #[test]
+    fn a_signature_is_rejected_with_a_different_secret() {
+        let signature = sign(&secret("secret"), "message");
+        assert!(!verify(&secret("another secret"), "message", &signature));
+    }
+
+    #[test]
+    fn a_malformed_signature_is_rejected() {
+        assert!(!verify(&secret("secret"), "message", "not hex"));
+    }
+}
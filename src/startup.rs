@@ -1,21 +1,37 @@
 use crate::{
-    configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
+    authentication::{cookie_key, reject_anonymous_users, RedisSessionStore, Sessions},
+    configuration::{DatabaseSettings, PostmarkWebhookSettings, Settings},
+    email_client::SharedEmailClient,
+    email_templates::EmailTemplates,
+    routes::{
+        add_suppression, admin_dashboard, confirm, export_metrics, export_suppressions,
+        follow_issue_link, import_suppressions, list_dead_letters, list_suppressed, log_out, login,
+        login_form, preview_template, prometheus_handle, publish_newsletter, purge_dead_letter,
+        receive_postmark_webhook, remove_suppression, requeue_dead_letter, subscribe, track_click,
+        track_open, unsubscribe, unsubscribe_form,
+    },
 };
 use anyhow::Ok;
-use axum::Router;
+use axum::{extract::FromRef, middleware, Router};
+use axum_extra::extract::cookie::Key;
 use redis::{aio::ConnectionManager, Client};
 use secrecy::{ExposeSecret, SecretString};
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, PgPool};
 use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Versioned schema migrations from `migrations/`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
 
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: PgPool,
-    pub redis: ConnectionManager,
-    pub email_client: EmailClient,
+    pub sessions: Sessions,
+    pub email_client: SharedEmailClient,
+    pub email_templates: EmailTemplates,
     pub base_url: ApplicationBaseUrl,
     pub hmac_secret: HmacSecret,
+    pub postmark_webhook: Option<PostmarkWebhookSettings>,
 }
 
 pub struct Application {
@@ -25,9 +41,26 @@ pub struct Application {
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+    pub async fn build(
+        configuration: Settings,
+        email_client: SharedEmailClient,
+    ) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let email_client = configuration.email_client.client();
+        if configuration.database.migrate_on_startup {
+            MIGRATOR.run(&connection_pool).await?;
+        }
+        // Before anything records a metric, or it would be lost.
+        prometheus_handle();
+        let redis = get_redis_connection(&configuration.redis_url).await?;
+        let sessions = Sessions::new(
+            RedisSessionStore::new(redis),
+            cookie_key(&HmacSecret(configuration.application.hmac_secret.clone())),
+        );
+        let postmark_webhook = configuration.email_client.postmark_webhook.clone();
+        let email_templates = EmailTemplates::new(
+            configuration.application.templates_directory,
+            connection_pool.clone(),
+        );
 
         let address = format!(
             "{}:{}",
@@ -39,11 +72,12 @@ impl Application {
         let router = build_router(
             connection_pool,
             email_client,
+            email_templates,
             configuration.application.base_url,
             configuration.application.hmac_secret,
-            configuration.redis_url,
-        )
-        .await?;
+            postmark_webhook,
+            sessions,
+        );
 
         Ok(Self {
             port,
@@ -52,8 +86,19 @@ impl Application {
         })
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        axum::serve(self.listener, self.router).await
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Serve requests until `shutdown` is cancelled, then stop accepting new
+    /// connections and wait for the open ones to complete.
+    pub async fn run_until_stopped(
+        self,
+        shutdown: CancellationToken,
+    ) -> Result<(), std::io::Error> {
+        axum::serve(self.listener, self.router)
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await
     }
 }
 
@@ -63,36 +108,310 @@ pub struct ApplicationBaseUrl(pub String);
 #[derive(Clone)]
 pub struct HmacSecret(pub SecretString);
 
-fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        Key::from_ref(&state.sessions)
+    }
+}
+
+impl FromRef<AppState> for Sessions {
+    fn from_ref(state: &AppState) -> Self {
+        state.sessions.clone()
+    }
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.db_pool.clone()
+    }
+}
+
+impl FromRef<AppState> for HmacSecret {
+    fn from_ref(state: &AppState) -> Self {
+        state.hmac_secret.clone()
+    }
+}
+
+pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(configuration.connect_options())
 }
 
-async fn build_router(
+pub async fn get_redis_connection(
+    redis_url: &SecretString,
+) -> Result<ConnectionManager, redis::RedisError> {
+    let redis_client = Client::open(redis_url.expose_secret())?;
+    ConnectionManager::new(redis_client).await
+}
+
+fn build_router(
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client: SharedEmailClient,
+    email_templates: EmailTemplates,
     base_url: String,
     hmac_secret: SecretString,
-    redis_url: SecretString,
-) -> Result<Router, anyhow::Error> {
-    use axum::routing::get;
-
-    let redis_client = Client::open(redis_url.expose_secret().to_string())?;
-    let redis = ConnectionManager::new(redis_client).await?;
+    postmark_webhook: Option<PostmarkWebhookSettings>,
+    sessions: Sessions,
+) -> Router {
+    use axum::routing::{delete, get, post};
 
     let app_state = AppState {
         db_pool,
-        redis,
+        sessions,
         email_client,
+        email_templates,
         base_url: ApplicationBaseUrl(base_url),
         hmac_secret: HmacSecret(hmac_secret),
+        postmark_webhook,
     };
 
-    let router = Router::new()
+    let admin_routes = Router::new()
+        .route("/dashboard", get(admin_dashboard))
+        .route("/logout", post(log_out))
+        .route("/newsletters", post(publish_newsletter))
+        .route("/templates/:name/preview", get(preview_template))
+        .route("/dead_letter_jobs", get(list_dead_letters))
+        .route(
+            "/dead_letter_jobs/:job_id/requeue",
+            post(requeue_dead_letter),
+        )
+        .route("/dead_letter_jobs/:job_id", delete(purge_dead_letter))
+        .route("/suppressions", get(list_suppressed).post(add_suppression))
+        .route(
+            "/suppressions/csv",
+            get(export_suppressions).post(import_suppressions),
+        )
+        .route("/suppressions/:entry", delete(remove_suppression))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.sessions.clone(),
+            reject_anonymous_users,
+        ));
+
+    Router::new()
         .route("/health", get(health_check))
-        .with_state(app_state);
-    Ok(router)
+        .route("/metrics", get(export_metrics))
+        .route("/login", get(login_form).post(login))
+        .route(
+            "/newsletters/:newsletter_issue_id/links/:link_index",
+            get(follow_issue_link),
+        )
+        .route("/subscriptions", post(subscribe))
+        .route("/subscriptions/confirm", get(confirm))
+        .route(
+            "/subscriptions/unsubscribe",
+            get(unsubscribe_form).post(unsubscribe),
+        )
+        .route("/t/o/:token", get(track_open))
+        .route("/t/c/:token", get(track_click))
+        .route("/webhooks/postmark", post(receive_postmark_webhook))
+        .nest("/admin", admin_routes)
+        .with_state(app_state)
 }
 
 async fn health_check() -> axum::http::StatusCode {
     axum::http::StatusCode::OK
 }
+
+#[cfg(test)]
+mod tests {
+    use super::build_router;
+    use crate::authentication::{create_user, MemorySessionStore, Sessions};
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, FileTransport, SharedEmailClient};
+    use crate::email_templates::EmailTemplates;
+    use axum::{
+        body::Body,
+        http::{header, HeaderValue, Method, Request, StatusCode},
+        Router,
+    };
+    use axum_extra::extract::cookie::Key;
+    use secrecy::SecretString;
+    use sqlx::{postgres::PgPoolOptions, PgPool};
+    use tower::ServiceExt;
+
+    async fn router() -> Router {
+        let db_pool = PgPoolOptions::new()
+            .connect_lazy("postgres://127.0.0.1/unused")
+            .unwrap();
+        router_with(db_pool, MemorySessionStore::default())
+    }
+
+    fn router_with(db_pool: PgPool, session_store: MemorySessionStore) -> Router {
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            FileTransport::new(std::env::temp_dir().join("unused.mbox")),
+        );
+        build_router(
+            db_pool.clone(),
+            SharedEmailClient::new(email_client),
+            EmailTemplates::new("src/templates".into(), db_pool),
+            "http://127.0.0.1:8000".into(),
+            SecretString::from("secret"),
+            None,
+            Sessions::new(session_store, Key::generate()),
+        )
+    }
+
+    #[tokio::test]
+    async fn anonymous_users_cannot_reach_the_dead_letter_jobs() {
+        let router = router().await;
+        let job_id = uuid::Uuid::new_v4();
+
+        for (method, uri) in [
+            (Method::GET, "/admin/dead_letter_jobs".to_string()),
+            (
+                Method::POST,
+                format!("/admin/dead_letter_jobs/{}/requeue", job_id),
+            ),
+            (
+                Method::DELETE,
+                format!("/admin/dead_letter_jobs/{}", job_id),
+            ),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+
+            let response = router.clone().oneshot(request).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::SEE_OTHER, "{}", uri);
+            assert_eq!(response.headers()[header::LOCATION], "/login", "{}", uri);
+        }
+    }
+
+    /// Log in as `username`, returning the response and the `Cookie` header it asks for.
+    async fn log_in(
+        router: &Router,
+        username: &str,
+        password: &str,
+    ) -> (axum::response::Response, Option<HeaderValue>) {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/login")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!(
+                "username={}&password={}",
+                username, password
+            )))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let cookie = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .map(|set_cookie| {
+                let set_cookie = set_cookie.to_str().unwrap();
+                set_cookie.split(';').next().unwrap().parse().unwrap()
+            });
+        (response, cookie)
+    }
+
+    async fn request(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        cookie: &HeaderValue,
+    ) -> axum::response::Response {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    fn assert_is_redirect_to(response: &axum::response::Response, location: &str) {
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], location);
+    }
+
+    #[sqlx::test]
+    async fn logged_in_users_reach_the_dashboard(pool: PgPool) {
+        create_user("alice", "correct horse".to_string().into(), &pool)
+            .await
+            .unwrap();
+        let router = router_with(pool, MemorySessionStore::default());
+
+        let (response, cookie) = log_in(&router, "alice", "correct+horse").await;
+
+        assert_is_redirect_to(&response, "/admin/dashboard");
+        let response = request(&router, Method::GET, "/admin/dashboard", &cookie.unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Welcome alice!"));
+    }
+
+    #[sqlx::test]
+    async fn a_failed_login_starts_no_session(pool: PgPool) {
+        create_user("alice", "correct horse".to_string().into(), &pool)
+            .await
+            .unwrap();
+        let router = router_with(pool, MemorySessionStore::default());
+
+        for (username, password) in [("alice", "wrong+horse"), ("bob", "correct+horse")] {
+            let (response, cookie) = log_in(&router, username, password).await;
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", username);
+            assert!(cookie.is_none(), "{}", username);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("Authentication failed"));
+        }
+    }
+
+    #[sqlx::test]
+    async fn expired_sessions_are_sent_back_to_login(pool: PgPool) {
+        create_user("alice", "correct horse".to_string().into(), &pool)
+            .await
+            .unwrap();
+        let router = router_with(
+            pool,
+            MemorySessionStore::expiring_after(std::time::Duration::from_millis(100)),
+        );
+        let (_, cookie) = log_in(&router, "alice", "correct+horse").await;
+        let cookie = cookie.unwrap();
+        let response = request(&router, Method::GET, "/admin/dashboard", &cookie).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let response = request(&router, Method::GET, "/admin/dashboard", &cookie).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    #[sqlx::test]
+    async fn logging_out_ends_the_session(pool: PgPool) {
+        create_user("alice", "correct horse".to_string().into(), &pool)
+            .await
+            .unwrap();
+        let router = router_with(pool, MemorySessionStore::default());
+        let (_, cookie) = log_in(&router, "alice", "correct+horse").await;
+        let cookie = cookie.unwrap();
+
+        let response = request(&router, Method::POST, "/admin/logout", &cookie).await;
+
+        assert_is_redirect_to(&response, "/login");
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.starts_with("session_id=;"), "{}", set_cookie);
+        // Replaying the old cookie does not bring the session back.
+        let response = request(&router, Method::GET, "/admin/dashboard", &cookie).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    #[tokio::test]
+    async fn anonymous_users_are_sent_to_login() {
+        let router = router().await;
+        let request = Request::builder()
+            .uri("/admin/dashboard")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = router.oneshot(request).await.unwrap();
+
+        assert_is_redirect_to(&response, "/login");
+    }
+}
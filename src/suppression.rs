@@ -0,0 +1,369 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Row};
+
+use crate::domain::SubscriberEmail;
+
+/// Header of the CSV export, and of imports.
+const CSV_HEADER: &str = "entry,reason,created_at";
+
+/// An address, or a whole domain, that must never be mailed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuppressionEntry {
+    /// Normalized, see [`SubscriberEmail::normalized`].
+    Address(String),
+    /// Lowercased, without the `@`.
+    Domain(String),
+}
+
+impl SuppressionEntry {
+    /// Parse `ursula@example.com` as an address, `example.com` or `@example.com` as a
+    /// domain.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if let Some(domain) = s.strip_prefix('@') {
+            return Self::parse_domain(domain);
+        }
+        if s.contains('@') {
+            let email = SubscriberEmail::parse(s.to_owned())?;
+            return Ok(Self::Address(email.normalized()));
+        }
+        Self::parse_domain(s)
+    }
+
+    fn parse_domain(domain: &str) -> Result<Self, String> {
+        let domain = domain.to_lowercase();
+        let is_valid_label = |label: &str| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        };
+        if domain.contains('.') && domain.split('.').all(is_valid_label) {
+            Ok(Self::Domain(domain))
+        } else {
+            Err(format!("{} is not a valid email address or domain", domain))
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            SuppressionEntry::Address(_) => "address",
+            SuppressionEntry::Domain(_) => "domain",
+        }
+    }
+
+    fn value(&self) -> &str {
+        match self {
+            SuppressionEntry::Address(value) | SuppressionEntry::Domain(value) => value,
+        }
+    }
+}
+
+impl std::fmt::Display for SuppressionEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value().fmt(f)
+    }
+}
+
+/// Why an entry is on the list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    HardBounce,
+    SpamComplaint,
+    /// Suppressed on the email provider's side, e.g. from its dashboard.
+    Provider,
+    /// Added by an admin.
+    Manual,
+}
+
+impl SuppressionReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::HardBounce => "hard_bounce",
+            SuppressionReason::SpamComplaint => "spam_complaint",
+            SuppressionReason::Provider => "provider",
+            SuppressionReason::Manual => "manual",
+        }
+    }
+}
+
+impl TryFrom<&str> for SuppressionReason {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "hard_bounce" => Ok(Self::HardBounce),
+            "spam_complaint" => Ok(Self::SpamComplaint),
+            "provider" => Ok(Self::Provider),
+            "manual" => Ok(Self::Manual),
+            other => Err(format!("{} is not a suppression reason", other)),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Suppression {
+    #[serde(serialize_with = "serialize_entry")]
+    pub entry: SuppressionEntry,
+    pub reason: SuppressionReason,
+    pub created_at: DateTime<Utc>,
+}
+
+fn serialize_entry<S: serde::Serializer>(
+    entry: &SuppressionEntry,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(entry)
+}
+
+/// Whether `email`, or its domain, is on the suppression list.
+#[tracing::instrument(skip(pool))]
+pub async fn is_suppressed(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM suppressions
+            WHERE (kind = 'address' AND entry = $1) OR (kind = 'domain' AND entry = $2)
+        )
+        "#,
+    )
+    .bind(email.normalized())
+    .bind(email.domain())
+    .fetch_one(pool)
+    .await
+}
+
+/// Add `entry` to the list. An entry that is already there keeps its original reason.
+#[tracing::instrument(skip(executor))]
+pub async fn suppress(
+    executor: impl PgExecutor<'_>,
+    entry: &SuppressionEntry,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO suppressions (kind, entry, reason, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(entry.kind())
+    .bind(entry.value())
+    .bind(reason.as_str())
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Remove `entry` from the list. Returns `false` if it was not there.
+#[tracing::instrument(skip(executor))]
+pub async fn unsuppress(
+    executor: impl PgExecutor<'_>,
+    entry: &SuppressionEntry,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(r#"DELETE FROM suppressions WHERE kind = $1 AND entry = $2"#)
+        .bind(entry.kind())
+        .bind(entry.value())
+        .execute(executor)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove `entry` from the list, unless an admin added it.
+#[tracing::instrument(skip(executor))]
+pub async fn lift_provider_suppression(
+    executor: impl PgExecutor<'_>,
+    entry: &SuppressionEntry,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"DELETE FROM suppressions WHERE kind = $1 AND entry = $2 AND reason <> 'manual'"#,
+    )
+    .bind(entry.kind())
+    .bind(entry.value())
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "List suppressions", skip(pool))]
+pub async fn list_suppressions(pool: &PgPool) -> Result<Vec<Suppression>, anyhow::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT kind, entry, reason, created_at
+        FROM suppressions
+        ORDER BY created_at DESC, entry
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|row| {
+            let kind: String = row.try_get("kind")?;
+            let entry: String = row.try_get("entry")?;
+            let reason: String = row.try_get("reason")?;
+            let entry = match kind.as_str() {
+                "domain" => SuppressionEntry::Domain(entry),
+                _ => SuppressionEntry::Address(entry),
+            };
+            Ok(Suppression {
+                entry,
+                reason: reason.as_str().try_into().map_err(anyhow::Error::msg)?,
+                created_at: row.try_get("created_at")?,
+            })
+        })
+        .collect()
+}
+
+/// Render `suppressions` as CSV, with a [`CSV_HEADER`] line.
+pub fn to_csv(suppressions: &[Suppression]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for suppression in suppressions {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            suppression.entry,
+            suppression.reason.as_str(),
+            suppression.created_at.to_rfc3339()
+        ));
+    }
+    csv
+}
+
+/// Parse CSV as produced by [`to_csv`].
+///
+/// Only the `entry` column is required; `reason` defaults to `manual` and `created_at`
+/// is ignored. The header line is optional.
+pub fn parse_csv(csv: &str) -> Result<Vec<(SuppressionEntry, SuppressionReason)>, String> {
+    let mut lines = csv.lines().enumerate().peekable();
+    let mut entry_column = 0;
+    let mut reason_column = Some(1);
+    if let Some((_, header)) = lines.peek() {
+        let columns: Vec<_> = fields(header).collect();
+        if let Some(entry) = columns.iter().position(|column| *column == "entry") {
+            entry_column = entry;
+            reason_column = columns.iter().position(|column| *column == "reason");
+            lines.next();
+        }
+    }
+
+    lines
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| {
+            let line_error = |e: String| format!("line {}: {}", n + 1, e);
+            let columns: Vec<_> = fields(line).collect();
+            let entry = columns
+                .get(entry_column)
+                .ok_or_else(|| line_error("missing entry".into()))?;
+            let entry = SuppressionEntry::parse(entry).map_err(line_error)?;
+            let reason = match reason_column.and_then(|column| columns.get(column)) {
+                Some(reason) if !reason.is_empty() => {
+                    SuppressionReason::try_from(*reason).map_err(line_error)?
+                }
+                _ => SuppressionReason::Manual,
+            };
+            Ok((entry, reason))
+        })
+        .collect()
+}
+
+/// Fields of a CSV line. Neither entries nor reasons contain commas, so quotes are
+/// only stripped.
+fn fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(',')
+        .map(|field| field.trim().trim_matches('"').trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_csv, to_csv, Suppression, SuppressionEntry, SuppressionReason};
+    use chrono::Utc;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn addresses_and_domains_are_told_apart_and_normalized() {
+        assert_eq!(
+            assert_ok!(SuppressionEntry::parse(" Ursula@Example.com ")),
+            SuppressionEntry::Address("ursula@example.com".into())
+        );
+        for domain in ["Example.com", "@example.com"] {
+            assert_eq!(
+                assert_ok!(SuppressionEntry::parse(domain)),
+                SuppressionEntry::Domain("example.com".into())
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        for entry in [
+            "",
+            "localhost",
+            "ursula@",
+            "exa mple.com",
+            "-example.com",
+            "a..com",
+        ] {
+            assert_err!(SuppressionEntry::parse(entry));
+        }
+    }
+
+    #[test]
+    fn the_csv_export_can_be_imported_back() {
+        let suppressions = vec![
+            Suppression {
+                entry: SuppressionEntry::Address("ursula@example.com".into()),
+                reason: SuppressionReason::HardBounce,
+                created_at: Utc::now(),
+            },
+            Suppression {
+                entry: SuppressionEntry::Domain("example.org".into()),
+                reason: SuppressionReason::Manual,
+                created_at: Utc::now(),
+            },
+        ];
+
+        let imported = assert_ok!(parse_csv(&to_csv(&suppressions)));
+
+        assert_eq!(
+            imported,
+            vec![
+                (
+                    SuppressionEntry::Address("ursula@example.com".into()),
+                    SuppressionReason::HardBounce
+                ),
+                (
+                    SuppressionEntry::Domain("example.org".into()),
+                    SuppressionReason::Manual
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_bare_list_of_entries_is_imported_as_manual_suppressions() {
+        let imported = assert_ok!(parse_csv("ursula@example.com\n\n\"example.org\"\n"));
+
+        assert_eq!(
+            imported,
+            vec![
+                (
+                    SuppressionEntry::Address("ursula@example.com".into()),
+                    SuppressionReason::Manual
+                ),
+                (
+                    SuppressionEntry::Domain("example.org".into()),
+                    SuppressionReason::Manual
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        let error = assert_err!(parse_csv(
+            "entry,reason\nursula@example.com,manual\nnope,manual"
+        ));
+        assert!(error.starts_with("line 3:"));
+    }
+}
@@ -0,0 +1,26 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+/// Log `e` and turn it into an opaque 500 response.
+pub fn e500<E>(e: E) -> Response
+where
+    E: std::fmt::Debug + std::fmt::Display,
+{
+    tracing::error!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        "Failed to handle the request"
+    );
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+/// Escape the characters that are significant in HTML text and attribute values.
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}